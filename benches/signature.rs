@@ -0,0 +1,149 @@
+//! Packed Signature vs the previous byte-array implementation, on the operations of the search hot loop.
+//! Run with `cargo bench --bench signature`.
+
+use std::hash::BuildHasher;
+
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/signature.rs"]
+mod signature;
+
+use signature::{FnvBuildHasher, Signature};
+
+/// The byte-array Signature that the packed one replaced, kept here as the benchmark baseline.
+mod legacy {
+    use std::hash::{Hash, Hasher};
+
+    #[repr(C)]
+    #[repr(align(8))]
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct Signature {
+        table: [u8; 26],
+    }
+
+    impl Signature {
+        pub fn new(word: &str) -> Self {
+            let mut table = [0; 26];
+            for c in word.bytes() {
+                table[(c - b'a') as usize] += 1;
+            }
+            Self { table }
+        }
+
+        #[inline(always)]
+        pub fn add(&mut self, other: &Signature) {
+            for (i, &count) in other.table.iter().enumerate() {
+                self.table[i] += count;
+            }
+        }
+
+        #[inline(always)]
+        pub fn sub(&mut self, other: &Signature) {
+            for (i, &count) in other.table.iter().enumerate() {
+                self.table[i] -= count;
+            }
+        }
+
+        #[inline(always)]
+        pub fn is_subset_of(&self, other: &Signature) -> bool {
+            for (a, b) in self.table.iter().zip(other.table.iter()) {
+                if a > b {
+                    return false;
+                }
+            }
+            true
+        }
+
+        #[inline(always)]
+        pub fn get_char_number(&self) -> usize {
+            let mut s = 0usize;
+            for i in 0..26 {
+                s += self.table[i] as usize;
+            }
+            s
+        }
+    }
+
+    impl Hash for Signature {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            let data = self.table.as_ptr();
+
+            unsafe {
+                state.write_u64((data as *const u64).read_unaligned());
+                state.write_u64((data.add(8) as *const u64).read_unaligned());
+                state.write_u64((data.add(16) as *const u64).read_unaligned());
+                state.write_u64((data.add(24) as *const u16).read_unaligned() as u64);
+            }
+        }
+    }
+}
+
+const TARGET: &str = "bazzecoleandantisatorareponetoperarotas";
+const WORDS: [&str; 8] = [
+    "bazze", "cole", "andanti", "sator", "arepo", "tenet", "quiz", "rotas",
+];
+
+fn bench_signature(c: &mut Criterion) {
+    let packed_target = Signature::new(TARGET);
+    let packed_words: Vec<Signature> = WORDS.iter().map(|w| Signature::new(w)).collect();
+    let legacy_target = legacy::Signature::new(TARGET);
+    let legacy_words: Vec<legacy::Signature> =
+        WORDS.iter().map(|w| legacy::Signature::new(w)).collect();
+
+    // The search loop: add a word, test it against the target, take it back
+    let mut group = c.benchmark_group("add_subset_sub");
+    group.bench_function(BenchmarkId::new("packed", WORDS.len()), |b| {
+        b.iter(|| {
+            let mut ws = Signature::new_empty();
+            let mut fits = 0;
+            for w in &packed_words {
+                ws.add(w);
+                if ws.is_subset_of(black_box(&packed_target)) {
+                    fits += 1;
+                } else {
+                    ws.sub(w);
+                }
+            }
+            black_box(fits)
+        })
+    });
+    group.bench_function(BenchmarkId::new("legacy", WORDS.len()), |b| {
+        b.iter(|| {
+            let mut ws = legacy::Signature::new(""); // Empty
+            let mut fits = 0;
+            for w in &legacy_words {
+                ws.add(w);
+                if ws.is_subset_of(black_box(&legacy_target)) {
+                    fits += 1;
+                } else {
+                    ws.sub(w);
+                }
+            }
+            black_box(fits)
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("char_number");
+    group.bench_function("packed", |b| {
+        b.iter(|| black_box(&packed_target).get_char_number())
+    });
+    group.bench_function("legacy", |b| {
+        b.iter(|| black_box(&legacy_target).get_char_number())
+    });
+    group.finish();
+
+    let hasher = FnvBuildHasher::default();
+    let mut group = c.benchmark_group("hash");
+    group.bench_function("packed", |b| {
+        b.iter(|| hasher.hash_one(black_box(&packed_target)))
+    });
+    group.bench_function("legacy", |b| {
+        b.iter(|| hasher.hash_one(black_box(&legacy_target)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_signature);
+criterion_main!(benches);
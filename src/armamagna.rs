@@ -1,17 +1,88 @@
+use std::collections::BTreeMap;
 use std::fs::OpenOptions;
-use std::io::{BufWriter, Write};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::io::{BufWriter, ErrorKind};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
-use crossbeam_channel::{Receiver, Sender, unbounded};
-use rayon::ThreadPoolBuilder;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
 use crate::combinations::RepeatedCombinationsWithSum;
-use crate::dictionarium::{Dictionarium, normalize_string};
-use crate::search;
+use crate::dictionarium::{Dictionarium, Section, count_blanks, normalize_string};
+use crate::filter::{WordFilter, WordList};
+use crate::format::{OutputFormat, OutputSink, RecordWriter, RunHeader, RunOptions};
+use crate::memo::MemoCache;
+use crate::pattern::WordPattern;
+use crate::progress::{self, ProgressFormat, ProgressReporter};
+use crate::sampling;
+use crate::scheduler;
+use crate::search::{self, Anagram, OutputMode, SearchStats, Tolerance, WordLimits};
 use crate::signature::Signature;
+use crate::sorter::{SORT_MEMORY_LIMIT, SortedOutput};
+use crate::sqlite::SqliteWriter;
+use crate::summary::RunSummary;
+use crate::{detail, result, status, trace};
+
+/// Resources shared by all the search tasks.
+struct SearchContext {
+    dictionarium: Arc<Dictionarium>,
+    target_signature: Arc<Signature>,
+    included_text: Arc<String>,
+    memo: Arc<MemoCache>,
+    slot_sections: Option<Arc<Vec<Section>>>, // Of the pattern, if any
+    partial: bool,
+    blanks: usize,
+    tolerance: Tolerance,
+    letter_bank: bool,
+    limits: WordLimits,
+}
+
+impl SearchContext {
+    /// Creates a searcher for the set_index-th length combination.
+    fn new_search_thread(
+        &self,
+        set_index: usize,
+        set: Vec<usize>,
+        sender: Sender<Anagram>,
+    ) -> search::SearchThread {
+        let mut search_thread = search::SearchThread::new(
+            Arc::clone(&self.dictionarium),
+            Arc::clone(&self.target_signature),
+            Arc::clone(&self.included_text),
+            set,
+            sender,
+            Arc::clone(&self.memo),
+        );
+        search_thread.set_set_index(set_index);
+        if let Some(slot_sections) = &self.slot_sections {
+            search_thread.set_slot_sections(Arc::clone(slot_sections));
+        }
+        search_thread.set_partial(self.partial);
+        search_thread.set_blanks(self.blanks);
+        search_thread.set_tolerance(self.tolerance);
+        search_thread.set_letter_bank(self.letter_bank);
+        search_thread.set_word_limits(self.limits);
+        search_thread
+    }
+}
+
+/// Where and how the IO thread writes the output lines.
+struct OutputSettings {
+    file_name: Option<String>, // "-" for stdout, None if there is nothing to write
+    format: OutputFormat,
+    header: RunHeader,
+    sorted: bool,
+    sets_number: usize,
+    lines_are_anagrams: bool, // False if the lines stand for several anagrams, or for none
+}
+
+/// What the IO thread wrote.
+struct IoOutcome {
+    lines_written: u64,
+    pipe_closed: bool, // The reader of stdout went away before the end, the remaining lines were dropped
+}
 
 /// The Rust version of ArmaMagna, quite faithful to the original C++ version
 pub struct ArmaMagna {
@@ -24,18 +95,37 @@ pub struct ArmaMagna {
     max_cardinality: u64,
     min_wordlength: u64,
     max_wordlength: u64,
+    memo_size: usize,
+    output_mode: OutputMode,
+    sample: Option<(u64, u64)>, // (anagrams to draw, seed)
+    sorted: bool,
+    output_format: OutputFormat,
+    progress_format: ProgressFormat,
+    summary_file: Option<String>,      // JSON run summary
+    expected_checksum: Option<String>, // Dictionary checksum required by a rerun
+    word_filter: WordFilter,           // Words removed from the dictionary
+    pattern: Option<WordPattern>,      // Shape of the anagrams, replacing the cardinality bounds
+    partial: bool,                     // Anagrams may leave letters of the target unused
+    max_leftover: Option<u64>, // Maximum number of unused letters in partial mode, None for any
+    tolerance: Tolerance,      // Letters near-anagrams may add or drop
+    letter_bank: bool,         // The target is a set of letters to reuse freely
+    required_letters: Signature, // Letters every phrase must contain in letter bank mode, once each
+    limits: WordLimits,        // Repetitions of a word and short words allowed in an anagram
 
     // Processed variables
     dictionary: Dictionarium, // Shared but read-only for threads (will be Arc-wrapped when needed)
     target_signature: Signature,
+    blanks: usize, // Blank tiles of the target text, standing for any letter
     included_text_signature: Signature,
     actual_target_signature: Signature, // actual = target - included
     included_words_number: u64,
     actual_min_cardinality: u64,
     actual_max_cardinality: u64,
     num_threads: u64,
+    summary: RunSummary, // Of the last run
 
     explored_sets: Arc<AtomicU64>, //⚛️Progress index, keeps track of how many search threads finished
+    finished_anagrams: Arc<AtomicU64>, //⚛️Anagrams found by the finished tasks
 }
 
 impl ArmaMagna {
@@ -50,21 +140,41 @@ impl ArmaMagna {
             max_cardinality: 3,
             min_wordlength: 1,
             max_wordlength: 30,
+            memo_size: 1 << 20,
+            output_mode: OutputMode::Anagrams,
+            sample: None,
+            sorted: false,
+            output_format: OutputFormat::Text,
+            progress_format: ProgressFormat::Text,
+            summary_file: None,
+            expected_checksum: None,
+            word_filter: WordFilter::new(),
+            pattern: None,
+            partial: false,
+            max_leftover: None,
+            tolerance: Tolerance::default(),
+            letter_bank: false,
+            required_letters: Signature::new_empty(),
+            limits: WordLimits::default(),
 
             dictionary: Dictionarium::new(),
             target_signature: Signature::new_empty(),
+            blanks: 0,
             included_text_signature: Signature::new_empty(),
             actual_target_signature: Signature::new_empty(),
             included_words_number: 0,
             actual_min_cardinality: 0,
             actual_max_cardinality: 0,
             num_threads: num_cpus::get() as u64,
+            summary: RunSummary::default(),
 
             explored_sets: Arc::new(AtomicU64::new(0)), //⚛️
+            finished_anagrams: Arc::new(AtomicU64::new(0)), //⚛️
         }
     }
 
     /// Sets the search options.
+    #[allow(clippy::too_many_arguments)]
     pub fn set_options(
         &mut self,
         text: &str,
@@ -86,11 +196,12 @@ impl ArmaMagna {
         Ok(())
     }
 
-    /// Sets the text to anagram.
+    /// Sets the text to anagram. Every '?' is a blank tile, standing for any letter.
     pub fn set_target_text(&mut self, text: &str) -> Result<(), String> {
         self.target_text = text.to_string();
         let processed_source_text = normalize_string(&self.target_text); // Processes the target text and computes its signature
-        self.target_signature = Signature::new(&processed_source_text);
+        self.target_signature = Signature::try_new(&processed_source_text)?;
+        self.blanks = count_blanks(&self.target_text);
         Ok(())
     }
 
@@ -106,7 +217,7 @@ impl ArmaMagna {
 
         // Processes the included text
         let processed_included_text = normalize_string(&self.included_text);
-        self.included_text_signature = Signature::new(&processed_included_text);
+        self.included_text_signature = Signature::try_new(&processed_included_text)?;
 
         // Computes the number of included words
         if self.included_text.is_empty() {
@@ -122,12 +233,12 @@ impl ArmaMagna {
         {
             return Err("The included text must be a subset of the target text".to_string());
         }
-        if self.target_signature == self.included_text_signature {
+        if self.target_signature == self.included_text_signature && self.blanks == 0 {
             return Err("The included is an anagram of the target text".to_string());
         }
 
         // actual = target - included
-        self.actual_target_signature = self.target_signature.clone();
+        self.actual_target_signature = self.target_signature;
         self.actual_target_signature
             .sub(&self.included_text_signature);
 
@@ -175,6 +286,211 @@ impl ArmaMagna {
         Ok(())
     }
 
+    /// Sets the maximum number of sub-problems kept in the shared memo cache (0 disables it).
+    pub fn set_memo_size(&mut self, entries: usize) {
+        self.memo_size = entries;
+    }
+
+    /// Sets what to do with the solutions: write the anagrams, only count them, or write them in compact form.
+    pub fn set_output_mode(&mut self, mode: OutputMode) {
+        self.output_mode = mode;
+    }
+
+    /// Writes only a uniform random sample of n anagrams. The same seed and dictionary always give the same sample.
+    pub fn set_sample(&mut self, n: u64, seed: u64) {
+        self.sample = Some((n, seed));
+    }
+
+    /// Writes the output in a deterministic order, independent of thread scheduling.
+    pub fn set_sorted(&mut self, sorted: bool) {
+        self.sorted = sorted;
+    }
+
+    /// Sets the file format of the output.
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.output_format = format;
+    }
+
+    /// Sets how progress is reported during the search.
+    pub fn set_progress_format(&mut self, format: ProgressFormat) {
+        self.progress_format = format;
+    }
+
+    /// Writes the run summary as JSON to the given file.
+    pub fn set_summary_file(&mut self, path: &str) {
+        self.summary_file = Some(path.to_string());
+    }
+
+    /// Searches only the anagrams with the shape of the pattern, words in the pattern order: one word per slot, of
+    /// the slot's length, with the letters known at some positions, e.g. "?a??? ?????e". Sets the cardinality, which
+    /// must be within the bounds of the options, like the slot lengths; must be set after the options.
+    pub fn set_pattern(&mut self, pattern: &str) -> Result<(), String> {
+        self.set_word_pattern(WordPattern::parse(pattern)?)
+    }
+
+    /// Searches only the anagrams made of words of the given lengths, in this order, e.g. [5, 3, 7].
+    pub fn set_lengths(&mut self, lengths: &[usize]) -> Result<(), String> {
+        self.set_word_pattern(WordPattern::from_lengths(lengths))
+    }
+
+    fn set_word_pattern(&mut self, pattern: WordPattern) -> Result<(), String> {
+        if !self.included_text.is_empty() {
+            return Err("A pattern can't be combined with an included text".to_string());
+        }
+        let lengths = pattern.get_lengths();
+        let words_number = lengths.len() as u64;
+        if words_number < self.min_cardinality {
+            return Err(format!(
+                "The pattern has {} words, the minimum cardinality is {}",
+                words_number, self.min_cardinality
+            ));
+        }
+        if words_number > self.max_cardinality {
+            return Err(format!(
+                "The pattern has {} words, the maximum cardinality is {}",
+                words_number, self.max_cardinality
+            ));
+        }
+        for (slot, &len) in lengths.iter().enumerate() {
+            if len < self.min_wordlength as usize || len > self.max_wordlength as usize {
+                return Err(format!(
+                    "Slot {} of the pattern ({}) has {} letters, outside the word length bounds ({},{})",
+                    slot + 1,
+                    pattern.get_slot_text(slot),
+                    len,
+                    self.min_wordlength,
+                    self.max_wordlength
+                ));
+            }
+        }
+        let letters_number: usize = lengths.iter().sum();
+        if letters_number != self.letters_number() {
+            return Err(format!(
+                "The pattern has {} letters, the text to anagram has {}",
+                letters_number,
+                self.letters_number()
+            ));
+        }
+        if !pattern
+            .get_known_letters()?
+            .is_subset_with_blanks(&self.target_signature, self.blanks)
+        {
+            return Err(
+                "The letters of the pattern must come from the text to anagram".to_string(),
+            );
+        }
+
+        // The pattern sets the cardinality (it can't be combined with an included text)
+        self.min_cardinality = words_number;
+        self.max_cardinality = words_number;
+        self.actual_min_cardinality = words_number;
+        self.actual_max_cardinality = words_number;
+        self.pattern = Some(pattern);
+        Ok(())
+    }
+
+    /// Treats the target text as a bank of letters that the words may reuse freely, as in spelling games, instead of
+    /// letters to use once each. Every phrase must contain the required letters; the cardinality and word length
+    /// bounds still apply. Must be set after the target text and the other modes, which it replaces.
+    pub fn set_letter_bank(&mut self, required_letters: &str) -> Result<(), String> {
+        if !self.included_text.is_empty() || self.pattern.is_some() {
+            return Err("A letter bank can't have an included text or a pattern".to_string());
+        }
+        if self.partial || !self.tolerance.is_exact() || self.blanks > 0 {
+            return Err(
+                "A letter bank can't be combined with partial anagrams, a tolerance or blank tiles"
+                    .to_string(),
+            );
+        }
+        let required = Signature::try_new(&normalize_string(required_letters))?.letter_set();
+        if !required.is_letter_subset_of(&self.target_signature) {
+            return Err("The required letters must come from the letter bank".to_string());
+        }
+
+        self.letter_bank = true;
+        self.required_letters = required;
+        self.dictionary.set_letter_bank(true);
+        Ok(())
+    }
+
+    /// Limits how many times a word may occur in an anagram, and how many short words it may have. The search
+    /// enforces them, so the anagrams beyond the limits are never built. Must be set after the pattern.
+    pub fn set_word_limits(&mut self, limits: WordLimits) -> Result<(), String> {
+        if limits.max_repeat.is_some() && self.pattern.is_some() {
+            return Err("A pattern can't limit the repetitions of a word".to_string());
+        }
+        self.limits = limits;
+        Ok(())
+    }
+
+    /// Returns the signature the search threads must cover: the required letters of a letter bank, otherwise the
+    /// target less the included text.
+    fn search_target(&self) -> Signature {
+        if self.letter_bank {
+            self.required_letters
+        } else {
+            self.actual_target_signature
+        }
+    }
+
+    /// Returns the number of letters of the anagrams: the ones of the target text, blanks included, less the included
+    /// text.
+    fn letters_number(&self) -> usize {
+        self.actual_target_signature.get_char_number() + self.blanks
+    }
+
+    /// Searches the word combinations that use some of the letters of the target, leaving at most max_leftover
+    /// letters unused (any number if None). The unused letters are reported with every anagram.
+    pub fn set_partial(&mut self, max_leftover: Option<u64>) -> Result<(), String> {
+        if self.pattern.is_some() {
+            return Err("A pattern needs all the letters, it can't be partial".to_string());
+        }
+        self.partial = true;
+        self.max_leftover = max_leftover;
+        Ok(())
+    }
+
+    /// Searches near-anagrams, whose words may add up to tolerance.add letters to the target and leave up to
+    /// tolerance.drop of its letters unused. The letters added and dropped are reported with every anagram.
+    /// Must be set after the target text, pattern and partial mode.
+    pub fn set_tolerance(&mut self, tolerance: Tolerance) -> Result<(), String> {
+        if self.pattern.is_some() {
+            return Err("A pattern fixes the word lengths, it can't have a tolerance".to_string());
+        }
+        if self.partial {
+            return Err(
+                "Partial anagrams already drop letters, they can't have a tolerance".to_string(),
+            );
+        }
+        if self.blanks > 0 {
+            return Err("Blank tiles can't be combined with a tolerance".to_string());
+        }
+        self.tolerance = tolerance;
+        self.dictionary.set_extra_letters(tolerance.add);
+        Ok(())
+    }
+
+    /// Removes the words matched by the filter from the dictionary before the search. The included text must not
+    /// contain any of them.
+    pub fn set_word_filter(&mut self, filter: WordFilter) {
+        self.word_filter = filter;
+    }
+
+    /// Sets the blocklist and allowlist applied while the dictionary is read, and to the included text.
+    pub fn set_word_lists(&mut self, blocklist: Option<WordList>, allowlist: Option<WordList>) {
+        self.dictionary.set_word_lists(blocklist, allowlist);
+    }
+
+    /// Makes the search fail if the dictionary checksum differs, i.e. if the word list changed since a previous run.
+    pub fn set_expected_checksum(&mut self, checksum: &str) {
+        self.expected_checksum = Some(checksum.to_string());
+    }
+
+    /// Returns the summary of the last run.
+    pub fn get_summary(&self) -> &RunSummary {
+        &self.summary
+    }
+
     /// Sets the desired number of search threads.
     pub fn set_threads_number(&mut self, n: u64) {
         self.num_threads = n;
@@ -183,41 +499,180 @@ impl ArmaMagna {
     /// Main function equivalent to C++ `anagram()`.
     /// Returns the number of anagrams found on success.
     pub fn anagram(&mut self) -> Result<u64, String> {
+        let run_start = Instant::now();
+        let mut summary = RunSummary::default();
+
         // Output settings
         self.print();
 
+        // The included words must pass the blocklist, the allowlist and the exclusions, like the dictionary words
+        for word in self.included_text.split_whitespace() {
+            if !self.dictionary.is_allowed(word, &normalize_string(word)) {
+                return Err(format!(
+                    "The included word \"{}\" is rejected by the blocklist or allowlist",
+                    word
+                ));
+            }
+            if self.word_filter.matches(word) {
+                return Err(format!(
+                    "The included word \"{}\" is excluded from the dictionary",
+                    word
+                ));
+            }
+        }
+
         // Reads the dictionary
+        let read_start = Instant::now();
         let words_read = self
             .dictionary
             .read_word_list(&self.dictionary_name, &self.target_text)?;
-        println!(
+        if !self.word_filter.is_empty() {
+            summary.words_excluded = self
+                .dictionary
+                .remove_words(|w| self.word_filter.matches(w));
+            status!("[*] Excluded {} words", summary.words_excluded);
+        }
+        status!(
             "[*] Read {} words, after filter {}\n",
             words_read,
             self.dictionary.get_reduced_words_number()
         );
+        summary.timings.dictionary = read_start.elapsed().as_secs_f64();
+        summary.dictionary_checksum = format!("{:016x}", self.dictionary.get_checksum());
+        if let Some(expected) = &self.expected_checksum
+            && *expected != summary.dictionary_checksum
+        {
+            return Err(format!(
+                "Dictionary {} has changed: checksum {}, expected {}",
+                self.dictionary_name, summary.dictionary_checksum, expected
+            ));
+        }
+        summary.sections = self.dictionary.get_section_stats();
+        summary.words_read = words_read;
+        summary.words_kept = self.dictionary.get_reduced_words_number();
+        summary.words_blocked = self.dictionary.get_blocked_words_number();
+        let planning_start = Instant::now();
+
+        let run_header = self.run_header();
 
         // Computes the power set from the word lengths that are available in the dictionary after filtering
         let available_lengths = self
             .dictionary
             .get_available_lengths(self.min_wordlength as usize, self.max_wordlength as usize);
 
-        let rcs = RepeatedCombinationsWithSum::new(
-            self.actual_target_signature.get_char_number(),
-            self.actual_min_cardinality as usize,
-            self.actual_max_cardinality as usize,
-            available_lengths,
-        );
+        let rcs = match &self.pattern {
+            // Any phrase within the bounds: its length is at most maxcard times the longest word
+            None if self.letter_bank => {
+                let longest = available_lengths.iter().copied().max().unwrap_or(1);
+                RepeatedCombinationsWithSum::new_with_min_sum(
+                    1,
+                    self.actual_max_cardinality as usize * longest,
+                    self.actual_min_cardinality as usize,
+                    self.actual_max_cardinality as usize,
+                    available_lengths,
+                )
+            }
+            Some(pattern) => RepeatedCombinationsWithSum::new_ordered(
+                self.letters_number(),
+                available_lengths,
+                pattern.get_lengths(),
+            ),
+            None if self.partial => {
+                let sum = self.letters_number();
+                RepeatedCombinationsWithSum::new_with_min_sum(
+                    sum.saturating_sub(self.max_leftover.unwrap_or(sum as u64) as usize),
+                    sum,
+                    self.actual_min_cardinality as usize,
+                    self.actual_max_cardinality as usize,
+                    available_lengths,
+                )
+            }
+            None if !self.tolerance.is_exact() => {
+                let sum = self.letters_number();
+                RepeatedCombinationsWithSum::new_with_min_sum(
+                    sum.saturating_sub(self.tolerance.drop),
+                    sum + self.tolerance.add,
+                    self.actual_min_cardinality as usize,
+                    self.actual_max_cardinality as usize,
+                    available_lengths,
+                )
+            }
+            None => RepeatedCombinationsWithSum::new(
+                self.letters_number(),
+                self.actual_min_cardinality as usize,
+                self.actual_max_cardinality as usize,
+                available_lengths,
+            ),
+        };
         let combinations_number = rcs.get_sets_number();
 
         // Reserve two threads: main + IO
-        let workers_number = (self.num_threads - 2).max(1);
-        println!("[*] Starting {} search threads", workers_number);
-        println!("[*] Covering {} length combinations\n", combinations_number);
+        let workers_number = self.num_threads.saturating_sub(2).max(1);
+
+        // Heavy length combinations are split so that the last tasks to run are the small ones
+        let slot_sections = self
+            .pattern
+            .as_ref()
+            .map(|pattern| Arc::new(pattern.slot_sections(&self.dictionary)));
+        let tasks = scheduler::plan_tasks(
+            &self.dictionary,
+            &self.search_target(),
+            &rcs,
+            slot_sections.as_deref().map(Vec::as_slice),
+            workers_number as usize,
+            self.partial || self.tolerance.drop > 0,
+            self.letter_bank,
+        );
+        let pending_tasks: Arc<Vec<AtomicUsize>> = Arc::new(
+            (0..combinations_number)
+                .map(|_| AtomicUsize::new(0))
+                .collect(),
+        );
+        for task in &tasks {
+            pending_tasks[task.set_index].fetch_add(1, Ordering::Relaxed);
+        }
+
+        // Sets without tasks can't cover some letter of the target, they are explored already
+        let infeasible_sets = pending_tasks
+            .iter()
+            .filter(|p| p.load(Ordering::Relaxed) == 0)
+            .count();
+        self.explored_sets
+            .store(infeasible_sets as u64, Ordering::Relaxed);
+        summary.sets_total = combinations_number as u64;
+        summary.sets_infeasible = infeasible_sets as u64;
+        summary.tasks = tasks.len() as u64;
+        summary.timings.planning = planning_start.elapsed().as_secs_f64();
+
+        if let Some(task) = tasks.first() {
+            detail!(
+                "[*] Largest task: {:?}, first slot {:?}, estimated cost {:.0}",
+                rcs.get_set(task.set_index),
+                task.first_slot,
+                task.cost
+            );
+        }
+        status!("[*] Starting {} search threads", workers_number);
+        status!(
+            "[*] Covering {} length combinations in {} tasks ({} infeasible)\n",
+            combinations_number,
+            tasks.len(),
+            infeasible_sets
+        );
 
         // Prepare the Arcs to share with workers
-        let dict_arc = Arc::new(std::mem::take(&mut self.dictionary)); //Moved
-        let actual_target_signature_arc = Arc::new(self.actual_target_signature.clone());
-        let included_text_arc = Arc::new(self.included_text.clone());
+        let context_arc = Arc::new(SearchContext {
+            dictionarium: Arc::new(std::mem::take(&mut self.dictionary)), //Moved
+            target_signature: Arc::new(self.search_target()),
+            included_text: Arc::new(self.included_text.clone()),
+            memo: Arc::new(MemoCache::new(self.memo_size)),
+            slot_sections,
+            partial: self.partial,
+            blanks: self.blanks,
+            tolerance: self.tolerance,
+            letter_bank: self.letter_bank,
+            limits: self.limits,
+        });
 
         // Build a rayon thread pool with the desired number of worker threads
         let pool = ThreadPoolBuilder::new()
@@ -226,33 +681,87 @@ impl ArmaMagna {
             .map_err(|e| format!("Failed to build thread pool: {}", e))?;
 
         // Create the crossbeam channel (unbounded). Producers will be clones of sender
-        let (sender, receiver): (Sender<String>, Receiver<String>) = unbounded();
+        let (sender, receiver): (Sender<Anagram>, Receiver<Anagram>) = unbounded();
 
-        // Spawn the IO thread which consumes from the receiver and writes to the output file
-        let of = self.output_file_name.clone();
-        let progress_clone = self.explored_sets.clone();
-        let io_handle =
-            thread::spawn(move || Self::io_loop(receiver, of, progress_clone, combinations_number));
+        // Spawn the IO thread which consumes from the receiver, writes to the output file (nothing to write when counting)
+        // and reports the progress
+        let output_settings = OutputSettings {
+            file_name: (self.output_mode != OutputMode::CountOnly)
+                .then(|| self.output_file_name.clone()),
+            format: self.output_format,
+            header: run_header,
+            sorted: self.sorted,
+            sets_number: combinations_number,
+            lines_are_anagrams: self.output_mode == OutputMode::Anagrams && self.sample.is_none(),
+        };
+        let completed_cost = Arc::new(AtomicU64::new(0f64.to_bits()));
+        let reporter = ProgressReporter::new(
+            self.progress_format,
+            self.explored_sets.clone(),
+            combinations_number as u64,
+            completed_cost.clone(),
+            tasks.iter().map(|t| t.cost).sum(),
+        );
+        let finished_anagrams_clone = self.finished_anagrams.clone();
+        let io_handle = thread::spawn(move || {
+            Self::io_loop(receiver, output_settings, reporter, finished_anagrams_clone)
+        });
+        // Sampling needs the anagram counts first: the anagrams are only built afterwards
+        let output_mode = if self.sample.is_some() {
+            OutputMode::CountOnly
+        } else {
+            self.output_mode
+        };
+
+        // Anagrams found per length combination
+        let set_counts: Arc<Vec<AtomicU64>> = Arc::new(
+            (0..combinations_number)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        );
 
+        let search_stats = Mutex::new(SearchStats::default());
         let timer_start = Instant::now();
 
-        // Scope the work so we block until all tasks are done.
-        pool.scope(|s| {
-            for i in 0..combinations_number {
-                let set = rcs.get_set(i).clone();
+        // Scope the work so we block until all tasks are done. FIFO keeps the largest-first order of the plan.
+        pool.scope_fifo(|s| {
+            for task in tasks {
+                let set = rcs.get_set(task.set_index).clone();
 
                 // Clone arcs & sender for move into task
-                let dict = Arc::clone(&dict_arc);
-                let actual_sig = Arc::clone(&actual_target_signature_arc);
-                let included_txt = Arc::clone(&included_text_arc);
+                let context = Arc::clone(&context_arc);
                 let task_sender = sender.clone();
                 let explored_sets_clone = self.explored_sets.clone();
+                let pending_tasks_clone = Arc::clone(&pending_tasks);
+                let set_counts_clone = Arc::clone(&set_counts);
+                let finished_anagrams_clone = self.finished_anagrams.clone();
+                let completed_cost_clone = Arc::clone(&completed_cost);
+                let search_stats = &search_stats;
 
-                s.spawn(move |_| {
+                s.spawn_fifo(move |_| {
+                    let task_start = Instant::now();
                     let mut search_thread =
-                        search::SearchThread::new(dict, actual_sig, included_txt, set, task_sender);
-                    search_thread.run();
-                    explored_sets_clone.fetch_add(1, Ordering::Relaxed);
+                        context.new_search_thread(task.set_index, set.clone(), task_sender);
+                    search_thread.set_first_slot_range(task.first_slot.clone());
+                    search_thread.set_output_mode(output_mode);
+                    let found = search_thread.run();
+                    search_stats.lock().unwrap().add(&search_thread.get_stats());
+                    set_counts_clone[task.set_index].fetch_add(found, Ordering::Relaxed);
+                    finished_anagrams_clone.fetch_add(found, Ordering::Relaxed);
+                    progress::add_completed_cost(&completed_cost_clone, task.cost);
+                    trace!(
+                        "[task] {:?}, first slot {:?}, estimated cost {:.0}: {} anagrams in {:.2?}",
+                        set,
+                        task.first_slot,
+                        task.cost,
+                        found,
+                        task_start.elapsed()
+                    );
+
+                    // A set is explored when its last task ends
+                    if pending_tasks_clone[task.set_index].fetch_sub(1, Ordering::AcqRel) == 1 {
+                        explored_sets_clone.fetch_add(1, Ordering::Relaxed);
+                    }
                 });
             }
             // When the scope ends, all spawned tasks are guaranteed to have completed,
@@ -261,86 +770,338 @@ impl ArmaMagna {
 
         let now = Instant::now();
         let elapsed = now.duration_since(timer_start);
-        println!("\n\n[*] Search time: {:.2?}", elapsed);
+        summary.timings.search = elapsed.as_secs_f64();
+
+        if self.sample.is_some() {
+            let sampling_start = Instant::now();
+            self.draw_samples(&pool, &context_arc, &rcs, &set_counts, &sender);
+            summary.timings.sampling = sampling_start.elapsed().as_secs_f64();
+        }
 
         drop(sender); // Drop the first sender to avoid deadlock
 
-        // Join the I/O thread
-        let thread_result = io_handle.join();
+        let output_start = Instant::now();
+        let IoOutcome {
+            lines_written,
+            pipe_closed,
+        } = Self::join_io_thread(io_handle)?;
+        summary.timings.output = output_start.elapsed().as_secs_f64();
+
+        // Once the reader has closed the pipe, the run ends quietly
+        if !pipe_closed {
+            status!("\n\n[*] Search time: {:.2?}", elapsed);
+        }
+
+        // In compact mode the lines are signature solutions, the threads know how many anagrams they stand for
+        let anagram_count = match self.output_mode {
+            OutputMode::Anagrams => lines_written,
+            OutputMode::CountOnly | OutputMode::Compact => set_counts
+                .iter()
+                .map(|c| c.load(Ordering::Relaxed))
+                .fold(0u64, |a, b| a.saturating_add(b)),
+        };
 
-        let anagram_count = match thread_result {
+        if self.output_mode == OutputMode::CountOnly {
+            self.print_counts(&rcs, &set_counts);
+        }
+
+        summary.sets_explored =
+            self.explored_sets.load(Ordering::Relaxed) - summary.sets_infeasible;
+        summary.search = search_stats.into_inner().unwrap();
+        summary.anagrams_found = anagram_count;
+        summary.output_truncated = pipe_closed;
+        summary.memo = context_arc.memo.get_stats();
+        summary.timings.total = run_start.elapsed().as_secs_f64();
+        if !pipe_closed {
+            summary.print();
+        }
+        if let Some(path) = &self.summary_file {
+            summary.write_json(path)?;
+        }
+        self.summary = summary;
+
+        Ok(anagram_count)
+    }
+
+    /// Draws n anagrams uniformly among the counted ones, builds them and sends them in index order.
+    /// Every length combination holding drawn indices is enumerated again, up to its last drawn index.
+    fn draw_samples(
+        &self,
+        pool: &ThreadPool,
+        context: &Arc<SearchContext>,
+        rcs: &RepeatedCombinationsWithSum,
+        set_counts: &[AtomicU64],
+        sender: &Sender<Anagram>,
+    ) {
+        let Some((n, seed)) = self.sample else {
+            return;
+        };
+        let counts: Vec<u64> = set_counts
+            .iter()
+            .map(|c| c.load(Ordering::Relaxed))
+            .collect();
+        let total = counts.iter().fold(0u64, |a, &b| a.saturating_add(b));
+        let targets = sampling::draw_indices(total, n, seed);
+        status!(
+            "\n[*] Sampling {} anagrams out of {} (seed {})",
+            targets.len(),
+            total,
+            seed
+        );
+
+        // Splits the global indices among the length combinations
+        let mut set_targets: Vec<Vec<u64>> = vec![Vec::new(); counts.len()];
+        let mut offsets = Vec::with_capacity(counts.len());
+        let mut offset = 0u64;
+        let mut t = 0;
+        for (i, &count) in counts.iter().enumerate() {
+            offsets.push(offset);
+            while t < targets.len() && targets[t] < offset + count {
+                set_targets[i].push(targets[t] - offset);
+                t += 1;
+            }
+            offset += count;
+        }
+
+        let samples: Mutex<Vec<(u64, Anagram)>> = Mutex::new(Vec::with_capacity(targets.len()));
+        pool.scope(|s| {
+            for (i, local_targets) in set_targets.into_iter().enumerate() {
+                if local_targets.is_empty() {
+                    continue;
+                }
+                let (samples, offsets) = (&samples, &offsets);
+                let (unused_sender, _) = unbounded();
+                let mut search_thread =
+                    context.new_search_thread(i, rcs.get_set(i).clone(), unused_sender);
+
+                s.spawn(move |_| {
+                    let found = search_thread.run_sample(local_targets);
+                    let mut samples = samples.lock().unwrap();
+                    samples.extend(found.into_iter().map(|(j, text)| {
+                        let anagram = Anagram { set_index: i, text };
+                        (offsets[i] + j, anagram)
+                    }));
+                });
+            }
+        });
+
+        let mut samples = samples.into_inner().unwrap();
+        samples.sort_unstable_by_key(|(index, _)| *index);
+        for (_, anagram) in samples {
+            let _ = sender.send(anagram);
+        }
+    }
+
+    /// Joins the IO thread, returning what it wrote.
+    fn join_io_thread(
+        handle: thread::JoinHandle<Result<IoOutcome, std::io::Error>>,
+    ) -> Result<IoOutcome, String> {
+        match handle.join() {
             // IO thread completed without panic, but might have returned an Err<io::Error>
-            Ok(io_res) => io_res.map_err(|e| format!("IO thread error: {}", e))?,
+            Ok(io_res) => io_res.map_err(|e| format!("IO thread error: {}", e)),
 
             // IO thread panicked (JoinHandle::join returns Err)
             Err(e) => {
                 if let Some(panic_msg) = e.downcast_ref::<&str>() {
-                    return Err(format!("IO thread panicked: {}", panic_msg));
+                    Err(format!("IO thread panicked: {}", panic_msg))
                 } else if let Some(panic_msg) = e.downcast_ref::<String>() {
-                    return Err(format!("IO thread panicked: {}", panic_msg));
+                    Err(format!("IO thread panicked: {}", panic_msg))
                 } else {
-                    return Err("IO thread panicked with unknown type.".to_string());
+                    Err("IO thread panicked with unknown type.".to_string())
                 }
             }
-        };
+        }
+    }
 
-        Ok(anagram_count)
+    /// Prints the anagram counts broken down by cardinality and by length combination.
+    fn print_counts(&self, rcs: &RepeatedCombinationsWithSum, set_counts: &[AtomicU64]) {
+        let mut by_cardinality: BTreeMap<u64, u64> = BTreeMap::new();
+        for (i, count) in set_counts.iter().enumerate() {
+            let cardinality = rcs.get_set(i).len() as u64 + self.included_words_number;
+            let entry = by_cardinality.entry(cardinality).or_insert(0);
+            *entry = entry.saturating_add(count.load(Ordering::Relaxed));
+        }
+
+        result!("\n[*] Anagrams by cardinality:");
+        for (cardinality, count) in &by_cardinality {
+            result!("{:<40}{}", format!("    {}", cardinality), count);
+        }
+
+        result!("\n[*] Anagrams by length combination:");
+        for (i, count) in set_counts.iter().enumerate() {
+            let count = count.load(Ordering::Relaxed);
+            if count > 0 {
+                result!("{:<40}{}", format!("    {:?}", rcs.get_set(i)), count);
+            }
+        }
     }
 
-    /// Consumes anagrams from the receiver and writes them to file. Returns anagram count or IO error.
-    fn io_loop(
-        receiver: Receiver<String>,
-        output_file_name: String,
-        explored_sets: Arc<AtomicU64>,
-        sets_number: usize,
-    ) -> Result<u64, std::io::Error> {
-        let mut last_display_time = Instant::now();
+    /// Describes the run for the header of the structured output formats.
+    fn run_header(&self) -> RunHeader {
+        RunHeader {
+            target: self.target_text.clone(),
+            included_text: self.included_text.clone(),
+            dictionary: self.dictionary_name.clone(),
+            dictionary_checksum: format!("{:016x}", self.dictionary.get_checksum()),
+            options: RunOptions {
+                mincard: self.min_cardinality,
+                maxcard: self.max_cardinality,
+                minwlen: self.min_wordlength,
+                maxwlen: self.max_wordlength,
+                output_mode: format!("{:?}", self.output_mode),
+                sorted: self.sorted,
+                sample: self.sample.map(|(n, _)| n),
+                seed: self.sample.map(|(_, seed)| seed),
+                pattern: self.pattern.as_ref().map(|p| p.to_string()),
+                partial: self.partial,
+                max_leftover: self.max_leftover,
+                blanks: self.blanks as u64,
+                tolerance: (!self.tolerance.is_exact()).then_some(self.tolerance),
+                letter_bank: self.letter_bank,
+                required_letters: self.letter_bank.then(|| self.required_letters.to_string()),
+                max_repeat: self.limits.max_repeat,
+                max_short: self.limits.max_short.map(|(n, _)| n),
+                short_len: self.limits.max_short.map(|(_, l)| l as u64),
+            },
+        }
+    }
 
+    /// Consumes anagrams from the receiver and writes them to file, or to stdout if the file name is "-".
+    /// Returns the line count or IO error. If stdout is closed by the reader (e.g. `| head`), stops quietly and
+    /// reports it: dropping the receiver makes the search threads stop too.
+    fn io_loop(
+        receiver: Receiver<Anagram>,
+        settings: OutputSettings,
+        mut reporter: ProgressReporter,
+        finished_anagrams: Arc<AtomicU64>,
+    ) -> Result<IoOutcome, std::io::Error> {
         // Open output file
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&output_file_name)?;
-
-        let mut writer = BufWriter::new(file);
+        let header = settings.header.clone();
+        let mut writer: Box<dyn OutputSink> = match settings.file_name.as_deref() {
+            None => Box::new(RecordWriter::new(
+                std::io::sink(),
+                OutputFormat::Text,
+                header,
+            )),
+            Some(file_name) if settings.format == OutputFormat::Sqlite => {
+                Box::new(SqliteWriter::open(file_name, header)?)
+            }
+            Some("-") => Box::new(RecordWriter::new(
+                BufWriter::new(std::io::stdout()),
+                settings.format,
+                header,
+            )),
+            Some(file_name) => {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(file_name)?;
+                Box::new(RecordWriter::new(
+                    BufWriter::new(file),
+                    settings.format,
+                    header,
+                ))
+            }
+        };
         let mut anagram_count: u64 = 0;
 
-        for anagram in receiver.iter() {
-            writeln!(writer, "{}", anagram)?;
-
-            anagram_count += 1;
-
-            // Update console every 1 second
-            let now = Instant::now();
-            if now.duration_since(last_display_time) >= Duration::from_millis(1000) {
-                print!(
-                    "\r[{}/{} sets] {}: {}{}",
-                    explored_sets.load(Ordering::Relaxed),
-                    sets_number,
-                    anagram_count,
-                    anagram,
-                    " ".repeat(30)
-                );
-                std::io::stdout().flush()?;
+        let result = Self::write_anagrams(
+            &receiver,
+            writer.as_mut(),
+            &settings,
+            &mut reporter,
+            &finished_anagrams,
+            &mut anagram_count,
+        );
+
+        if let Err(e) = &result
+            && e.kind() == ErrorKind::BrokenPipe
+        {
+            return Ok(IoOutcome {
+                lines_written: anagram_count,
+                pipe_closed: true,
+            });
+        }
+
+        let anagrams_found = if settings.lines_are_anagrams {
+            anagram_count
+        } else {
+            finished_anagrams.load(Ordering::Relaxed)
+        };
+        reporter.finish(anagrams_found);
+
+        match result {
+            Err(e) => Err(e),
+            Ok(()) => Ok(IoOutcome {
+                lines_written: anagram_count,
+                pipe_closed: false,
+            }),
+        }
+    }
+
+    /// Writes the anagrams received until the channel is closed, counting them, and reports the progress.
+    /// In sorted mode nothing is written until the channel is closed: the lines are collected per length combination
+    /// and written at the end, combinations in order and lines sorted within each one.
+    fn write_anagrams(
+        receiver: &Receiver<Anagram>,
+        writer: &mut dyn OutputSink,
+        settings: &OutputSettings,
+        reporter: &mut ProgressReporter,
+        finished_anagrams: &AtomicU64,
+        anagram_count: &mut u64,
+    ) -> Result<(), std::io::Error> {
+        let mut sorted_output = settings
+            .sorted
+            .then(|| SortedOutput::new(settings.sets_number, SORT_MEMORY_LIMIT));
+        let anagrams_found = |anagram_count: u64| {
+            if settings.lines_are_anagrams {
+                anagram_count
+            } else {
+                finished_anagrams.load(Ordering::Relaxed)
+            }
+        };
+
+        writer.write_header()?;
+
+        // Wakes up at least once per report interval, so that progress is reported even when nothing is found
+        loop {
+            let anagram = match receiver.recv_timeout(reporter.time_to_next_report()) {
+                Ok(anagram) => anagram,
+                Err(RecvTimeoutError::Timeout) => {
+                    reporter.report(anagrams_found(*anagram_count), None);
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            *anagram_count += 1;
+
+            if reporter.is_due() {
+                reporter.report(anagrams_found(*anagram_count), Some(&anagram.text));
                 writer.flush()?; //Flush periodically on file
-                last_display_time = now;
+            }
+
+            match &mut sorted_output {
+                Some(sorted_output) => sorted_output.push(anagram.set_index, anagram.text)?,
+                None => writer.write_line(&anagram.text)?,
             }
         }
 
-        // Final flush after the channel is exhausted
-        writer.flush()?;
+        if let Some(sorted_output) = sorted_output {
+            sorted_output.write_to(|line| writer.write_line(line))?;
+        }
 
-        Ok(anagram_count)
+        // Closes the format and flushes after the channel is exhausted
+        writer.finish()
     }
 
     // Debug print function
     pub fn print(&self) {
-        println!("\nArmaMagna multi-threaded anagrammer engine\n");
+        status!("\nArmaMagna multi-threaded anagrammer engine\n");
 
-        println!("{:<40}{}", "[*] Source text:", self.target_text);
-        println!("{:<40}{}", "[*] Dictionary:", self.dictionary_name);
-        println!(
+        status!("{:<40}{}", "[*] Source text:", self.target_text);
+        status!("{:<40}{}", "[*] Dictionary:", self.dictionary_name);
+        status!(
             "{:<40}{}",
             "[*] Included text:",
             if self.included_text.is_empty() {
@@ -349,46 +1110,91 @@ impl ArmaMagna {
                 &self.included_text
             }
         );
-        println!(
-            "{:<40}({},{})",
-            "[*] Cardinality:", self.min_cardinality, self.max_cardinality
-        );
-        println!(
+        match &self.pattern {
+            Some(pattern) => status!("{:<40}{}", "[*] Pattern:", pattern),
+            None => status!(
+                "{:<40}({},{})",
+                "[*] Cardinality:",
+                self.min_cardinality,
+                self.max_cardinality
+            ),
+        }
+        status!(
             "{:<40}({},{})",
-            "[*] Word lengths:", self.min_wordlength, self.max_wordlength
+            "[*] Word lengths:",
+            self.min_wordlength,
+            self.max_wordlength
         );
-        println!("{:<40}{}", "[*] Estimated concurrency:", num_cpus::get());
-        println!("{:<40}{}", "[*] Threads to launch:", self.num_threads);
-        println!();
+        status!("{:<40}{}", "[*] Estimated concurrency:", num_cpus::get());
+        status!("{:<40}{}", "[*] Threads to launch:", self.num_threads);
+        status!("{:<40}{}", "[*] Memo cache size:", self.memo_size);
+        status!("{:<40}{:?}", "[*] Output mode:", self.output_mode);
+        if self.partial {
+            match self.max_leftover {
+                Some(n) => status!("{:<40}at most {} letters left", "[*] Partial anagrams:", n),
+                None => status!("{:<40}any letters left", "[*] Partial anagrams:"),
+            }
+        }
+        if !self.tolerance.is_exact() {
+            status!("{:<40}{}", "[*] Tolerance:", self.tolerance);
+        }
+        if self.letter_bank {
+            status!(
+                "{:<40}{} (required: {})",
+                "[*] Letter bank:",
+                self.target_signature.letter_set(),
+                self.required_letters
+            );
+        }
+        if let Some(n) = self.limits.max_repeat {
+            status!("{:<40}{}", "[*] Repetitions of a word:", n);
+        }
+        if let Some((n, l)) = self.limits.max_short {
+            status!("{:<40}at most {} of length <= {}", "[*] Short words:", n, l);
+        }
+        if let Some((n, seed)) = self.sample {
+            status!("{:<40}{} (seed {})", "[*] Random sample:", n, seed);
+        }
+        status!("{:<40}{}", "[*] Sorted output:", self.sorted);
+        status!("{:<40}{:?}", "[*] Output format:", self.output_format);
+        if !self.word_filter.is_empty() {
+            status!("{:<40}{}", "[*] Excluded:", self.word_filter.describe());
+        }
+        for (name, description) in self.dictionary.describe_word_lists() {
+            status!("{:<40}{}", format!("[*] {}:", name), description);
+        }
+        status!();
 
-        println!(
-            "{:<40}{}",
-            "[*] Target signature:",
-            self.target_signature.to_string()
-        );
-        println!(
+        status!("{:<40}{}", "[*] Target signature:", self.target_signature);
+        if self.blanks > 0 {
+            status!("{:<40}{}", "[*] Blank tiles:", self.blanks);
+        }
+        status!(
             "{:<40}{}",
-            "[*] Included words number:", self.included_words_number
+            "[*] Included words number:",
+            self.included_words_number
         );
-        println!(
+        status!(
             "{:<40}{}",
             "[*] Included text signature:",
             if self.included_text.is_empty() {
                 "<void>".to_string()
             } else {
-                format!("{}", self.included_text_signature.to_string())
+                self.included_text_signature.to_string()
             }
         );
-        println!(
+        status!(
             "{:<40}{}",
             "[*] Actual target signature:",
-            self.actual_target_signature.to_string()
+            self.actual_target_signature
         );
-        println!(
+        status!(
             "{:<40}({},{})",
-            "[*] Actual cardinality:", self.actual_min_cardinality, self.actual_max_cardinality
+            "[*] Actual cardinality:",
+            self.actual_min_cardinality,
+            self.actual_max_cardinality
         );
-        println!();
+        status!();
     }
 }
 
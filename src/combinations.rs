@@ -1,12 +1,14 @@
 use std::fmt;
 
-/// Generate repeated combinations of `values` whose sum is equal to `sum`.
+/// Generate repeated combinations of `values` whose sum is equal to `sum` (or between `min_sum` and `sum`).
 #[derive(Debug)]
 pub struct RepeatedCombinationsWithSum {
     sum: usize,
+    min_sum: usize,
     min_cardinality: usize,
     max_cardinality: usize,
     values: Vec<usize>,
+    lengths: Option<Vec<usize>>, // Ordered lengths: the i-th item may only take the i-th length
     sets: Vec<Vec<usize>>,
 }
 
@@ -17,14 +19,29 @@ impl RepeatedCombinationsWithSum {
         min_cardinality: usize,
         max_cardinality: usize,
         values: Vec<usize>,
+    ) -> Self {
+        Self::new_with_min_sum(sum, sum, min_cardinality, max_cardinality, values)
+    }
+
+    /// Constructor for the combinations whose sum is between min_sum and sum, e.g. the words that use some letters
+    /// of the target but not all of them.
+    pub fn new_with_min_sum(
+        min_sum: usize,
+        sum: usize,
+        min_cardinality: usize,
+        max_cardinality: usize,
+        values: Vec<usize>,
     ) -> Self {
         debug_assert!(sum > 0 && min_cardinality > 0 && max_cardinality > 0 && !values.is_empty());
+        debug_assert!(min_sum <= sum);
 
         let mut rcs = Self {
             sum,
+            min_sum,
             min_cardinality,
             max_cardinality,
             values,
+            lengths: None,
             sets: Vec::new(),
         };
         rcs.generate_sets();
@@ -32,6 +49,24 @@ impl RepeatedCombinationsWithSum {
         rcs
     }
 
+    /// Constructor for an ordered sequence of lengths, such as the words of a pattern: the only set generated is the
+    /// sequence itself, in its order, provided that its lengths are among the values and add up to the sum.
+    pub fn new_ordered(sum: usize, values: Vec<usize>, lengths: Vec<usize>) -> Self {
+        debug_assert!(sum > 0 && !lengths.is_empty());
+
+        let mut rcs = Self {
+            sum,
+            min_sum: sum,
+            min_cardinality: lengths.len(),
+            max_cardinality: lengths.len(),
+            values,
+            lengths: Some(lengths),
+            sets: Vec::new(),
+        };
+        rcs.generate_sets();
+        rcs
+    }
+
     /// Returns the number of sets found
     pub fn get_sets_number(&self) -> usize {
         self.sets.len()
@@ -71,12 +106,21 @@ impl RepeatedCombinationsWithSum {
         // Terminal case
         debug_assert!(pos <= k);
         if pos == k {
-            if items_sum == self.sum {
+            if items_sum >= self.min_sum {
                 self.sets.push(solution[..k].to_vec());
             }
             return;
         }
 
+        // Ordered lengths: a single candidate per position
+        if let Some(len) = self.lengths.as_ref().map(|lengths| lengths[pos]) {
+            if self.values.contains(&len) {
+                solution[pos] = len;
+                self.combine(n, k, solution, pos + 1, start, items_sum + len);
+            }
+            return;
+        }
+
         // Recursive part
         for i in start..n {
             solution[pos] = self.values[i];
@@ -126,6 +170,26 @@ mod tests {
         assert_eq!(rcs.get_sets_number(), 0); // no possible set sums to 5
     }
 
+    #[test]
+    fn test_min_sum() {
+        let rcs = RepeatedCombinationsWithSum::new_with_min_sum(4, 5, 1, 2, vec![2, 3]);
+        let sets: Vec<&Vec<usize>> = (0..rcs.get_sets_number()).map(|i| rcs.get_set(i)).collect();
+        assert_eq!(sets, vec![&vec![2, 2], &vec![2, 3]]);
+    }
+
+    #[test]
+    fn test_ordered_lengths() {
+        let rcs = RepeatedCombinationsWithSum::new_ordered(12, vec![2, 5, 7], vec![5, 2, 5]);
+        assert_eq!(rcs.get_sets_number(), 1);
+        assert_eq!(rcs.get_set(0), &vec![5, 2, 5]); // Not sorted
+
+        // A missing length or a wrong sum give no set
+        let rcs = RepeatedCombinationsWithSum::new_ordered(12, vec![2, 7], vec![5, 2, 5]);
+        assert_eq!(rcs.get_sets_number(), 0);
+        let rcs = RepeatedCombinationsWithSum::new_ordered(13, vec![2, 5, 7], vec![5, 2, 5]);
+        assert_eq!(rcs.get_sets_number(), 0);
+    }
+
     #[test]
     fn test_single_value_multiple_times() {
         let values = vec![1];
@@ -0,0 +1,174 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use fxhash::FxHashSet;
+
+/// Formats the alternatives for one word of a signature solution: the word itself if there is only one,
+/// `{w1|w2|...}` otherwise.
+pub fn format_group(words: &[String]) -> String {
+    if words.len() == 1 {
+        words[0].clone()
+    } else {
+        format!("{{{}}}", words.join("|"))
+    }
+}
+
+/// Formats the included text as a group of one: quoted if it has several words, so that it expands as a whole,
+/// like in the regular output.
+pub fn format_included(text: &str) -> String {
+    if text.contains(char::is_whitespace) {
+        format!("\"{}\"", text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Splits a compact line into its groups of alternatives. Bare words and quoted texts are groups of one.
+fn parse_line(line: &str) -> Result<Vec<Vec<&str>>, String> {
+    let mut groups = Vec::new();
+    let mut rest = line.trim();
+
+    while !rest.is_empty() {
+        if let Some(inner) = rest.strip_prefix('{') {
+            let end = inner
+                .find('}')
+                .ok_or_else(|| format!("Unclosed group in line: {}", line))?;
+            groups.push(inner[..end].split('|').collect());
+            rest = inner[end + 1..].trim_start();
+        } else if let Some(inner) = rest.strip_prefix('"') {
+            let end = inner
+                .find('"')
+                .ok_or_else(|| format!("Unclosed quote in line: {}", line))?;
+            groups.push(vec![&inner[..end]]);
+            rest = inner[end + 1..].trim_start();
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            groups.push(vec![&rest[..end]]);
+            rest = rest[end..].trim_start();
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Expands a compact line into its anagrams: words sorted alphabetically and space-joined, each one only once,
+/// exactly like the regular output. If ordered, the words keep the order of their groups, like the output of a
+/// pattern search.
+pub fn expand_line(line: &str, ordered: bool) -> Result<Vec<String>, String> {
+    let groups = parse_line(line)?;
+    if groups.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut anagrams = Vec::new();
+    let mut anagram_set = FxHashSet::default();
+    let mut indices = vec![0; groups.len()];
+
+    // Odometer over the cartesian product of the groups
+    loop {
+        let mut words: Vec<&str> = groups.iter().zip(&indices).map(|(g, &i)| g[i]).collect();
+        if !ordered {
+            words.sort_unstable();
+        }
+
+        let anagram = words.join(" ");
+        if anagram_set.insert(anagram.clone()) {
+            anagrams.push(anagram);
+        }
+
+        let mut pos = groups.len();
+        loop {
+            if pos == 0 {
+                return Ok(anagrams);
+            }
+            pos -= 1;
+            indices[pos] += 1;
+            if indices[pos] < groups[pos].len() {
+                break;
+            }
+            indices[pos] = 0;
+        }
+    }
+}
+
+/// Expands a file written in compact mode into a regular anagram file. Returns the number of anagrams written.
+pub fn expand_file(
+    input_file_name: &str,
+    output_file_name: &str,
+    ordered: bool,
+) -> Result<u64, String> {
+    let input =
+        File::open(input_file_name).map_err(|_| format!("Cannot open file {}", input_file_name))?;
+    let output = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output_file_name)
+        .map_err(|e| format!("Cannot open file {}: {}", output_file_name, e))?;
+
+    let mut writer = BufWriter::new(output);
+    let mut anagram_count: u64 = 0;
+
+    for line in BufReader::new(input).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        for anagram in expand_line(&line, ordered)? {
+            writeln!(writer, "{}", anagram).map_err(|e| e.to_string())?;
+            anagram_count += 1;
+        }
+    }
+
+    writer.flush().map_err(|e| e.to_string())?;
+    Ok(anagram_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn test_format_group() {
+        assert_eq!(format_group(&strings(&["bar"])), "bar");
+        assert_eq!(format_group(&strings(&["bar", "bra"])), "{bar|bra}");
+        assert_eq!(format_included("zio"), "zio");
+        assert_eq!(format_included("zio ada"), "\"zio ada\"");
+    }
+
+    #[test]
+    fn test_expand_line() {
+        let anagrams = expand_line("{bar|bra} {man|nam}", false).unwrap();
+        assert_eq!(
+            anagrams,
+            strings(&["bar man", "bar nam", "bra man", "bra nam"])
+        );
+
+        // Bare words are sorted with the others
+        let anagrams = expand_line("zeta {bar|bra}", false).unwrap();
+        assert_eq!(anagrams, strings(&["bar zeta", "bra zeta"]));
+
+        // A quoted text is a single word
+        let anagrams = expand_line("\"zio ada\" {bar|bra}", false).unwrap();
+        assert_eq!(anagrams, strings(&["bar zio ada", "bra zio ada"]));
+
+        // Unless the groups are ordered
+        let anagrams = expand_line("zeta {bar|bra}", true).unwrap();
+        assert_eq!(anagrams, strings(&["zeta bar", "zeta bra"]));
+    }
+
+    #[test]
+    fn test_expand_line_repeated_group() {
+        // A repeated signature expands into multisets, each only once
+        let anagrams = expand_line("{ab|ba} {ab|ba}", false).unwrap();
+        assert_eq!(anagrams, strings(&["ab ab", "ab ba", "ba ba"]));
+    }
+
+    #[test]
+    fn test_expand_line_errors() {
+        assert!(expand_line("{bar|bra man", false).is_err());
+        assert!(expand_line("\"zio ada bar", false).is_err());
+        assert!(expand_line("   ", false).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// Set when stdout carries the anagrams: messages for the user then go to stderr.
+static STDERR_MODE: AtomicBool = AtomicBool::new(false);
+
+/// 0: quiet (errors only), 1: normal, 2: verbose (-v), 3: very verbose (-vv).
+static VERBOSITY: AtomicU8 = AtomicU8::new(1);
+
+/// Routes the user messages (banner, progress, summaries) to stderr instead of stdout.
+pub fn set_stderr_mode(enabled: bool) {
+    STDERR_MODE.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns true if the user messages go to stderr.
+pub fn is_stderr_mode() -> bool {
+    STDERR_MODE.load(Ordering::Relaxed)
+}
+
+/// Sets how much is printed.
+pub fn set_verbosity(verbosity: u8) {
+    VERBOSITY.store(verbosity, Ordering::Relaxed);
+}
+
+/// Returns how much is printed.
+pub fn get_verbosity() -> u8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// Prints a line of user messages, like println!, on stdout or stderr depending on the mode, if the verbosity
+/// is at least the given level.
+#[macro_export]
+macro_rules! say {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::console::get_verbosity() >= $level {
+            $crate::result!($($arg)*)
+        }
+    };
+}
+
+/// Prints a line of results, like println!, on stdout or stderr depending on the mode. Shown even with --quiet.
+#[macro_export]
+macro_rules! result {
+    ($($arg:tt)*) => {
+        if $crate::console::is_stderr_mode() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// Prints a line of user messages, hidden by --quiet.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => { $crate::say!(1, $($arg)*) };
+}
+
+/// Prints a line of details, shown with -v.
+#[macro_export]
+macro_rules! detail {
+    ($($arg:tt)*) => { $crate::say!(2, $($arg)*) };
+}
+
+/// Prints a line of debugging information, shown with -vv.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::say!(3, $($arg)*) };
+}
+
+/// Overwrites the progress line. Console errors are ignored: progress is not worth failing the run for.
+pub fn print_progress(line: &str) {
+    if is_stderr_mode() {
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\r{}", line);
+        let _ = stderr.flush();
+    } else {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "\r{}", line);
+        let _ = stdout.flush();
+    }
+}
@@ -1,24 +1,44 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::fs::File;
+use std::hash::Hasher;
 use std::io::{BufRead, BufReader};
 
-use crate::signature::{FnvBuildHasher, Signature};
+use crate::filter::WordList;
+use crate::signature::{FnvBuildHasher, FnvHasher, Signature};
 
+use serde::Serialize;
 use unicode_normalization::UnicodeNormalization;
 
 pub const MAX_WORD_LENGTH: usize = 45;
 pub type Section = HashMap<Signature, Vec<String>, FnvBuildHasher>;
 
+/// Identifies normalize_string() in run manifests: a change of normalization changes the results of a search.
+pub const NORMALIZATION_PROFILE: &str = "nfd-alphabetic-lowercase";
+
 /// Normalizes a string to ASCII non-accented  lower-case characters.
 pub fn normalize_string(s: &str) -> String {
-    let sn = s
-        .nfd()
+    s.nfd()
         .filter(|c| c.is_alphabetic())
         .collect::<String>()
-        .to_lowercase();
+        .to_lowercase()
+}
+
+/// A blank tile in the target text, standing for any letter. normalize_string() drops it, like any non-letter.
+pub const BLANK: char = '?';
 
-    return sn;
+/// Counts the blank tiles of a target text.
+pub fn count_blanks(s: &str) -> usize {
+    s.chars().filter(|&c| c == BLANK).count()
+}
+
+/// Size of a dictionary section before and after filtering on the target.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SectionStats {
+    pub length: usize,
+    pub words_read: u64,
+    pub words_kept: u64,
+    pub signatures: u64,
 }
 
 /// The dictionary object mapping signatures to their corresponding words. Divided in sections, one per word length,
@@ -28,7 +48,15 @@ pub struct Dictionarium {
     words_number: u64,
     reduced_words_number: u64,
     longest_word_length: usize,
+    checksum: u64,          // FNV-1a of the word list lines, each one followed by '\n'
+    words_read: Vec<u64>,   // index = word length, words read before filtering
     sections: Vec<Section>, // index = word length
+    coverages: Vec<Signature>, // index = word length, maximum count of every letter among the section's signatures
+    blocklist: Option<WordList>,
+    allowlist: Option<WordList>,
+    blocked_words_number: u64, // Words fitting the target but rejected by the lists
+    extra_letters: usize,      // Letters a word may have beyond the target, for near-anagrams
+    letter_bank: bool,         // The target is a set of letters that words may reuse freely
 }
 
 /// Returns an empty dictionary.
@@ -38,7 +66,15 @@ impl Default for Dictionarium {
             words_number: 0,
             reduced_words_number: 0,
             longest_word_length: 0,
+            checksum: 0,
+            words_read: vec![0; MAX_WORD_LENGTH + 1],
             sections: vec![HashMap::default(); MAX_WORD_LENGTH + 1],
+            coverages: vec![Signature::new_empty(); MAX_WORD_LENGTH + 1],
+            blocklist: None,
+            allowlist: None,
+            blocked_words_number: 0,
+            extra_letters: 0,
+            letter_bank: false,
         }
     }
 }
@@ -49,6 +85,50 @@ impl Dictionarium {
         Self::default()
     }
 
+    /// Keeps the words with up to extra_letters letters that the target lacks while reading the word list, so that
+    /// near-anagrams can add them.
+    pub fn set_extra_letters(&mut self, extra_letters: usize) {
+        self.extra_letters = extra_letters;
+    }
+
+    /// Makes read_word_list() keep the words spelled with the letters of the target, however many times each one
+    /// occurs.
+    pub fn set_letter_bank(&mut self, letter_bank: bool) {
+        self.letter_bank = letter_bank;
+    }
+
+    /// Sets the lists applied while reading the word list: blocklisted words are skipped, and so are the words
+    /// missing from the allowlist, if any.
+    pub fn set_word_lists(&mut self, blocklist: Option<WordList>, allowlist: Option<WordList>) {
+        self.blocklist = blocklist;
+        self.allowlist = allowlist;
+    }
+
+    /// Returns true if the lists let a word through. normalized must be normalize_string(word).
+    pub fn is_allowed(&self, word: &str, normalized: &str) -> bool {
+        let blocked = self
+            .blocklist
+            .as_ref()
+            .is_some_and(|list| list.contains(word, normalized));
+        let allowed = self
+            .allowlist
+            .as_ref()
+            .is_none_or(|list| list.contains(word, normalized));
+        allowed && !blocked
+    }
+
+    /// Describes the lists for the banner, if any.
+    pub fn describe_word_lists(&self) -> Vec<(&'static str, String)> {
+        let mut lists = Vec::new();
+        if let Some(list) = &self.blocklist {
+            lists.push(("Blocklist", list.describe()));
+        }
+        if let Some(list) = &self.allowlist {
+            lists.push(("Allowlist", list.describe()));
+        }
+        lists
+    }
+
     /// Reads a word list from a file and builds the sections.
     pub fn read_word_list(
         &mut self,
@@ -61,12 +141,17 @@ impl Dictionarium {
         let reader = BufReader::new(file);
 
         //Computes the target text signature
-        let normalized_target_text = normalize_string(&target_text);
-        let target_signature = Signature::new(&normalized_target_text);
+        let normalized_target_text = normalize_string(target_text);
+        let target_signature = Signature::try_new(&normalized_target_text)?;
+        let extra_letters = count_blanks(target_text) + self.extra_letters;
 
         //Reads the wordlist line by line
+        let mut hasher = FnvHasher::default();
         for line in reader.lines() {
             let line = line.map_err(|e| e.to_string())?;
+            hasher.write(line.as_bytes());
+            hasher.write(b"\n");
+
             let normalized_word = normalize_string(&line);
             if normalized_word.is_empty() {
                 continue; //Skip empty normalized words
@@ -84,9 +169,27 @@ impl Dictionarium {
             //Computes the word's signature
             let ws = Signature::new(&normalized_word);
             self.words_number += 1;
+            self.words_read[word_length] += 1;
+
+            //If the word is not a subset of the target, blanks and extra letters standing in for the missing ones, skips it
+            let fits = if self.letter_bank {
+                ws.is_letter_subset_of(&target_signature)
+            } else {
+                ws.is_subset_with_blanks(&target_signature, extra_letters)
+            };
+            if !fits {
+                continue;
+            }
+
+            //Skips the words rejected by the blocklist or allowlist
+            if !self.is_allowed(&line, &normalized_word) {
+                self.blocked_words_number += 1;
+                continue;
+            }
 
-            //If the word is not a subset of the target, skips it
-            if !ws.is_subset_of(&target_signature) {
+            //Skips duplicate lines, so that every anagram can be counted exactly once
+            let words = self.sections[word_length].entry(ws).or_default();
+            if words.contains(&line) {
                 continue;
             }
 
@@ -97,20 +200,71 @@ impl Dictionarium {
             }
 
             //Pushes the word in the right section, with the corresponding signature-key
-            self.sections[word_length]
-                .entry(ws)
-                .or_insert_with(Vec::new)
-                .push(line);
+            words.push(line);
+            self.coverages[word_length].union(&ws);
         }
 
+        self.checksum = hasher.finish();
         Ok(self.words_number)
     }
 
+    /// Removes the words for which is_excluded returns true, then refreshes the coverages and counters.
+    /// Returns the number of words removed.
+    pub fn remove_words(&mut self, is_excluded: impl Fn(&str) -> bool) -> u64 {
+        let mut removed = 0;
+        for section in &mut self.sections {
+            section.retain(|_, words| {
+                let before = words.len();
+                words.retain(|w| !is_excluded(w));
+                removed += (before - words.len()) as u64;
+                !words.is_empty()
+            });
+        }
+
+        self.reduced_words_number -= removed;
+        for (len, section) in self.sections.iter().enumerate() {
+            let mut coverage = Signature::new_empty();
+            for ws in section.keys() {
+                coverage.union(ws);
+            }
+            self.coverages[len] = coverage;
+        }
+        self.longest_word_length = (0..=MAX_WORD_LENGTH)
+            .rev()
+            .find(|&len| !self.sections[len].is_empty())
+            .unwrap_or(0);
+
+        removed
+    }
+
+    /// Returns the checksum of the last word list read, to identify the exact dictionary a run used.
+    pub fn get_checksum(&self) -> u64 {
+        self.checksum
+    }
+
+    /// Returns the number of words that fit the target but were rejected by the blocklist or allowlist.
+    pub fn get_blocked_words_number(&self) -> u64 {
+        self.blocked_words_number
+    }
+
     /// Returns the number of words in the dictionary after filtering.
     pub fn get_reduced_words_number(&self) -> u64 {
         self.reduced_words_number
     }
 
+    /// Returns the size of every section that had words before filtering.
+    pub fn get_section_stats(&self) -> Vec<SectionStats> {
+        (0..=MAX_WORD_LENGTH)
+            .filter(|&len| self.words_read[len] > 0)
+            .map(|len| SectionStats {
+                length: len,
+                words_read: self.words_read[len],
+                words_kept: self.sections[len].values().map(|w| w.len() as u64).sum(),
+                signatures: self.sections[len].len() as u64,
+            })
+            .collect()
+    }
+
     /// Returns a section of the dictionary (a hashmap mapping 1 signature --> multiple words)
     pub fn get_section(&self, section_number: usize) -> &Section {
         &self.sections[section_number]
@@ -119,7 +273,17 @@ impl Dictionarium {
     /// Returns the words corresponding to a certain signature.
     pub fn get_words(&self, ws: &Signature) -> &Vec<String> {
         let characters_number = ws.get_char_number();
-        self.sections[characters_number as usize].get(ws).unwrap() //Returns the set of words associated to ws
+        self.sections[characters_number].get(ws).unwrap() //Returns the set of words associated to ws
+    }
+
+    /// Returns the letters that words of the given lengths can cover at most, one word per length.
+    /// If a letter of the target is missing here, no combination of such words can be an anagram.
+    pub fn get_coverage(&self, lengths: &[usize]) -> Signature {
+        let mut coverage = Signature::new_empty();
+        for &len in lengths {
+            coverage.add_saturating(&self.coverages[len]);
+        }
+        coverage
     }
 
     /// Returns the dictionary sections that still contain words after filtering.
@@ -167,7 +331,7 @@ mod tests {
 
         let mut dict = Dictionarium::new();
         let result = dict
-            .read_word_list(tmp_file.path().to_str().unwrap(), &target_text)
+            .read_word_list(tmp_file.path().to_str().unwrap(), target_text)
             .unwrap();
 
         // Check the number of words read
@@ -176,7 +340,7 @@ mod tests {
 
         // Check that all words in sections are subset of the source
         for len in dict.get_available_lengths(1, 30) {
-            for (_sig, words) in dict.get_section(len) {
+            for words in dict.get_section(len).values() {
                 for word in words {
                     let normalized_word = normalize_string(word);
                     let sig = Signature::new(&normalized_word);
@@ -187,6 +351,129 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_duplicate_words_are_skipped() {
+        let mut tmp_file = NamedTempFile::new().unwrap();
+        writeln!(tmp_file, "bar").unwrap();
+        writeln!(tmp_file, "bar").unwrap();
+        writeln!(tmp_file, "bra").unwrap();
+
+        let mut dict = Dictionarium::new();
+        let read = dict
+            .read_word_list(tmp_file.path().to_str().unwrap(), "bar")
+            .unwrap();
+
+        assert_eq!(read, 3);
+        assert_eq!(dict.get_reduced_words_number(), 2);
+        assert_eq!(dict.get_words(&Signature::new("abr")).len(), 2);
+        assert_eq!(
+            dict.get_section_stats(),
+            vec![SectionStats {
+                length: 3,
+                words_read: 3,
+                words_kept: 2,
+                signatures: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn test_prefilter_modes() {
+        let mut tmp_file = NamedTempFile::new().unwrap();
+        for word in ["bar", "bard", "bird", "bride"] {
+            writeln!(tmp_file, "{}", word).unwrap();
+        }
+
+        let mut dict = Dictionarium::new();
+        dict.read_word_list(tmp_file.path().to_str().unwrap(), "bar ?")
+            .unwrap();
+
+        assert_eq!(count_blanks("b?r ?"), 2);
+        assert_eq!(dict.get_reduced_words_number(), 2);
+        assert!(dict.get_section(4).contains_key(&Signature::new("abdr")));
+        assert!(!dict.get_section(4).contains_key(&Signature::new("bdir")));
+
+        // Near-anagrams adding a letter keep the same words as a blank
+        let mut dict = Dictionarium::new();
+        dict.set_extra_letters(1);
+        dict.read_word_list(tmp_file.path().to_str().unwrap(), "bar")
+            .unwrap();
+        assert_eq!(dict.get_reduced_words_number(), 2);
+
+        // A letter bank reuses its letters but adds none
+        let mut dict = Dictionarium::new();
+        dict.set_letter_bank(true);
+        dict.read_word_list(tmp_file.path().to_str().unwrap(), "abdir")
+            .unwrap();
+        assert_eq!(dict.get_reduced_words_number(), 3);
+        assert!(!dict.get_section(5).contains_key(&Signature::new("bdeir")));
+    }
+
+    #[test]
+    fn test_remove_words() {
+        let mut tmp_file = NamedTempFile::new().unwrap();
+        for word in ["bar", "bra", "Mab", "ab", "c"] {
+            writeln!(tmp_file, "{}", word).unwrap();
+        }
+
+        let mut dict = Dictionarium::new();
+        dict.read_word_list(tmp_file.path().to_str().unwrap(), "barmabc")
+            .unwrap();
+
+        let removed = dict.remove_words(|w| w == "bra" || w == "Mab" || w == "c");
+        assert_eq!(removed, 3);
+        assert_eq!(dict.get_reduced_words_number(), 2);
+        assert_eq!(
+            dict.get_words(&Signature::new("abr")),
+            &vec!["bar".to_string()]
+        );
+        assert!(dict.get_section(1).is_empty());
+        assert_eq!(dict.get_coverage(&[3]).to_string(), "abr");
+        assert_eq!(dict.get_available_lengths(1, 30), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_word_lists() {
+        let mut tmp_file = NamedTempFile::new().unwrap();
+        for word in ["bar", "bra", "rab", "Arb"] {
+            writeln!(tmp_file, "{}", word).unwrap();
+        }
+        let mut blocklist_file = NamedTempFile::new().unwrap();
+        writeln!(blocklist_file, "BRA").unwrap();
+        let mut allowlist_file = NamedTempFile::new().unwrap();
+        writeln!(allowlist_file, "bar\nbra\narb").unwrap();
+
+        let mut dict = Dictionarium::new();
+        dict.set_word_lists(
+            Some(WordList::read(blocklist_file.path().to_str().unwrap(), false).unwrap()),
+            Some(WordList::read(allowlist_file.path().to_str().unwrap(), false).unwrap()),
+        );
+        dict.read_word_list(tmp_file.path().to_str().unwrap(), "bar")
+            .unwrap();
+
+        // "bra" is blocked, "rab" is not allowed
+        assert_eq!(dict.get_words(&Signature::new("abr")), &vec!["bar", "Arb"]);
+        assert_eq!(dict.get_blocked_words_number(), 2);
+        assert!(!dict.is_allowed("Bra", "bra"));
+    }
+
+    #[test]
+    fn test_get_coverage() {
+        let mut tmp_file = NamedTempFile::new().unwrap();
+        writeln!(tmp_file, "ab").unwrap();
+        writeln!(tmp_file, "bb").unwrap();
+        writeln!(tmp_file, "abc").unwrap();
+
+        let mut dict = Dictionarium::new();
+        dict.read_word_list(tmp_file.path().to_str().unwrap(), "aabbbc")
+            .unwrap();
+
+        assert_eq!(dict.get_coverage(&[2]).to_string(), "abb");
+        assert_eq!(dict.get_coverage(&[2, 2]).to_string(), "aabbbb");
+        assert_eq!(dict.get_coverage(&[2, 3]).to_string(), "aabbbc");
+        assert!(!Signature::new("c").is_subset_of(&dict.get_coverage(&[2, 2])));
+    }
+
     #[test]
     fn test_get_words_and_sections() {
         let mut tmp_file = NamedTempFile::new().unwrap();
@@ -205,7 +492,7 @@ mod tests {
 
         for len in lengths {
             let section = dict.get_section(len);
-            for (_sig, words) in section {
+            for words in section.values() {
                 for word in words {
                     assert!(word.len() == len);
                 }
@@ -0,0 +1,223 @@
+use std::collections::HashSet;
+use std::fs;
+
+use regex::Regex;
+
+use crate::dictionarium::normalize_string;
+
+/// Words to drop from the dictionary after loading. Listed words are compared on their normalized form, so "Città"
+/// also excludes "citta"; patterns are regular expressions matched against the words as written in the word list,
+/// so that "^[A-Z]" excludes capitalized names. Stems exclude the words starting with them, such as the inflections
+/// of a source word.
+#[derive(Debug, Default)]
+pub struct WordFilter {
+    words: HashSet<String>, // Normalized
+    stems: Vec<String>,     // Normalized
+    patterns: Vec<Regex>,
+}
+
+impl WordFilter {
+    /// Constructor, excluding nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Excludes a word.
+    pub fn add_word(&mut self, word: &str) {
+        let normalized = normalize_string(word);
+        if !normalized.is_empty() {
+            self.words.insert(normalized);
+        }
+    }
+
+    /// Excludes the words sharing at least their first stem_length letters with the given word, e.g. with a stem
+    /// length of 5, "andanti" excludes "andante" and "andando". Shorter words are only excluded themselves.
+    pub fn add_stem(&mut self, word: &str, stem_length: usize) {
+        let normalized = normalize_string(word);
+        match normalized.char_indices().nth(stem_length) {
+            Some((end, _)) => self.stems.push(normalized[..end].to_string()),
+            None if normalized.chars().count() == stem_length => self.stems.push(normalized),
+            None => self.add_word(&normalized),
+        }
+    }
+
+    /// Excludes the words of a file, one per line (see read_list()).
+    pub fn add_words_file(&mut self, path: &str) -> Result<(), String> {
+        for word in read_list(path)? {
+            self.add_word(&word);
+        }
+        Ok(())
+    }
+
+    /// Excludes the words matching a regular expression.
+    pub fn add_pattern(&mut self, pattern: &str) -> Result<(), String> {
+        let regex =
+            Regex::new(pattern).map_err(|e| format!("Invalid pattern {}: {}", pattern, e))?;
+        self.patterns.push(regex);
+        Ok(())
+    }
+
+    /// Returns true if nothing is excluded.
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.stems.is_empty() && self.patterns.is_empty()
+    }
+
+    /// Returns true if a word of the word list must be excluded.
+    pub fn matches(&self, word: &str) -> bool {
+        if !self.words.is_empty() || !self.stems.is_empty() {
+            let normalized = normalize_string(word);
+            if self.words.contains(&normalized)
+                || self
+                    .stems
+                    .iter()
+                    .any(|s| normalized.starts_with(s.as_str()))
+            {
+                return true;
+            }
+        }
+        self.patterns.iter().any(|p| p.is_match(word))
+    }
+
+    /// Describes the filter for the banner.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} words, {} stems, {} patterns",
+            self.words.len(),
+            self.stems.len(),
+            self.patterns.len()
+        )
+    }
+}
+
+/// A blocklist or allowlist, applied while the dictionary is read. Entries are compared on their normalized form,
+/// or exactly as written (accents and case included) if the list is case-exact.
+#[derive(Debug)]
+pub struct WordList {
+    words: HashSet<String>,
+    case_exact: bool,
+}
+
+impl WordList {
+    /// Reads a list from a file, one word per line (see read_list()).
+    pub fn read(path: &str, case_exact: bool) -> Result<Self, String> {
+        let mut list = Self {
+            words: HashSet::new(),
+            case_exact,
+        };
+        for word in read_list(path)? {
+            list.insert(&word);
+        }
+        Ok(list)
+    }
+
+    fn insert(&mut self, word: &str) {
+        if self.case_exact {
+            self.words.insert(word.to_string());
+        } else {
+            self.words.insert(normalize_string(word));
+        }
+    }
+
+    /// Returns true if the list holds a word. normalized must be normalize_string(word).
+    pub fn contains(&self, word: &str, normalized: &str) -> bool {
+        if self.case_exact {
+            self.words.contains(word.trim())
+        } else {
+            self.words.contains(normalized)
+        }
+    }
+
+    /// Describes the list for the banner.
+    pub fn describe(&self) -> String {
+        if self.case_exact {
+            format!("{} words (case-exact)", self.words.len())
+        } else {
+            format!("{} words", self.words.len())
+        }
+    }
+}
+
+/// Reads the words of a list file, one per line. Empty lines and lines starting with '#' are ignored.
+fn read_list(path: &str) -> Result<Vec<String>, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Cannot read file {}: {}", path, e))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_words_are_matched_on_normalized_form() {
+        let mut filter = WordFilter::new();
+        assert!(filter.is_empty());
+
+        filter.add_word("Città");
+        assert!(filter.matches("citta"));
+        assert!(filter.matches("CITTÀ"));
+        assert!(!filter.matches("cittadino"));
+
+        let mut tmp_file = NamedTempFile::new().unwrap();
+        writeln!(tmp_file, "# Names\nMario\n\n  zelo  ").unwrap();
+        filter
+            .add_words_file(tmp_file.path().to_str().unwrap())
+            .unwrap();
+        assert!(filter.matches("mario"));
+        assert!(filter.matches("zelo"));
+        assert!(!filter.matches("# Names"));
+    }
+
+    #[test]
+    fn test_patterns() {
+        let mut filter = WordFilter::new();
+        filter.add_pattern("^[A-Z]").unwrap();
+        filter.add_pattern("zz").unwrap();
+
+        assert!(filter.matches("Mario"));
+        assert!(filter.matches("bazza"));
+        assert!(!filter.matches("mario"));
+        assert_eq!(filter.describe(), "0 words, 0 stems, 2 patterns");
+
+        assert!(filter.add_pattern("[a-").is_err());
+    }
+
+    #[test]
+    fn test_stems() {
+        let mut filter = WordFilter::new();
+        filter.add_stem("Andanti", 5);
+        filter.add_stem("zelo", 5);
+
+        assert!(filter.matches("andante"));
+        assert!(filter.matches("andando"));
+        assert!(filter.matches("andanti"));
+        assert!(!filter.matches("anda"));
+        assert!(filter.matches("zelo"));
+        assert!(!filter.matches("zelota"));
+        assert_eq!(filter.describe(), "1 words, 1 stems, 0 patterns");
+    }
+
+    #[test]
+    fn test_word_list_case_exact() {
+        let mut tmp_file = NamedTempFile::new().unwrap();
+        writeln!(tmp_file, "Roma\ncittà").unwrap();
+        let path = tmp_file.path().to_str().unwrap();
+
+        let list = WordList::read(path, false).unwrap();
+        assert!(list.contains("ROMA", "roma"));
+        assert!(list.contains("Citta", "citta"));
+
+        let list = WordList::read(path, true).unwrap();
+        assert!(list.contains("Roma", "roma"));
+        assert!(!list.contains("roma", "roma"));
+        assert!(!list.contains("citta", "citta"));
+        assert_eq!(list.describe(), "2 words (case-exact)");
+    }
+}
@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dictionarium::{BLANK, normalize_string};
+use crate::search::Tolerance;
+use crate::signature::Signature;
+
+/// File format of the anagram output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Text,   // One anagram per line, words separated by spaces
+    Ndjson, // The run header on the first line, then one JSON record per line
+    Json,   // A single JSON object: {"run": header, "anagrams": [records]}
+    Csv,    // The run header as a # comment, then a header row and one row per anagram
+    Sqlite, // Indexed tables in an SQLite database, see sqlite.rs
+}
+
+/// Search options recorded in the run header.
+#[derive(Serialize, Clone, Debug)]
+pub struct RunOptions {
+    pub mincard: u64,
+    pub maxcard: u64,
+    pub minwlen: u64,
+    pub maxwlen: u64,
+    pub output_mode: String,
+    pub sorted: bool,
+    pub sample: Option<u64>,
+    pub seed: Option<u64>,
+    pub pattern: Option<String>,
+    pub partial: bool,
+    pub max_leftover: Option<u64>, // None: any number of letters may be left
+    pub blanks: u64,               // Blank tiles ('?') of the target
+    pub tolerance: Option<Tolerance>, // Of near-anagrams, None for exact ones
+    pub letter_bank: bool,         // The target is a bank of reusable letters
+    pub required_letters: Option<String>, // Letters every phrase contains, in letter bank mode
+    pub max_repeat: Option<u64>,   // Times a word may occur
+    pub max_short: Option<u64>,    // Words of length short_len or less
+    pub short_len: Option<u64>,
+}
+
+/// Describes a run, so that structured outputs can be traced back to the query and dictionary that produced them.
+#[derive(Serialize, Clone, Debug)]
+pub struct RunHeader {
+    pub target: String,
+    pub included_text: String,
+    pub dictionary: String,
+    pub dictionary_checksum: String, // Dictionarium::get_checksum(), hexadecimal
+    pub options: RunOptions,
+}
+
+/// One anagram with its metadata. Words are listed in output order, the other fields follow the same order.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Record<'a> {
+    pub words: Vec<&'a str>,
+    pub cardinality: usize,
+    pub length_set: Vec<usize>, // Sorted lengths of the normalized words
+    pub normalized: Vec<String>,
+    pub sources: Vec<Option<&'a str>>, // Dictionary of each word, null for the included text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leftover: Option<String>, // Letters of the target left unused, sorted, in partial mode only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blanks: Option<String>, // Letters the blank tiles stood for, sorted, if the target has blanks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added: Option<String>, // Letters a near-anagram adds to the target, sorted, with a tolerance only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dropped: Option<String>, // Letters of the target a near-anagram leaves out, sorted, with a tolerance only
+}
+
+/// Destination of the output lines, whatever the format.
+pub trait OutputSink {
+    /// Writes what comes before the first record.
+    fn write_header(&mut self) -> io::Result<()>;
+
+    /// Writes an output line (a space-joined anagram) as a record.
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
+
+    /// Makes the records written so far visible to readers.
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Writes what comes after the last record and flushes.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Turns output lines into records.
+pub struct RecordBuilder {
+    dictionary: String,
+    included_words: Vec<String>, // Normalized
+    target: Signature,           // Of the whole target text, blanks aside
+    partial: bool,
+    blanks: usize,
+    tolerant: bool, // Near-anagrams, see edits()
+}
+
+impl RecordBuilder {
+    /// Constructor
+    pub fn new(header: &RunHeader) -> Self {
+        Self {
+            dictionary: header.dictionary.clone(),
+            included_words: header
+                .included_text
+                .split_whitespace()
+                .map(normalize_string)
+                .collect(),
+            target: Signature::new(&normalize_string(&header.target)),
+            partial: header.options.partial,
+            blanks: header.options.blanks as usize,
+            tolerant: header.options.tolerance.is_some(),
+        }
+    }
+
+    /// Returns the letters of the target that the normalized words leave unused in partial mode, followed by a '?'
+    /// per unused blank.
+    pub fn leftover<S: AsRef<str>>(&self, normalized: &[S]) -> Option<String> {
+        if !self.partial {
+            return None;
+        }
+        let letters = Self::letters(normalized);
+        let blanks_used = letters.difference(&self.target).get_char_number();
+        let mut leftover = self.target.difference(&letters).to_string();
+        leftover.extend(std::iter::repeat_n(BLANK, self.blanks - blanks_used));
+        Some(leftover)
+    }
+
+    /// Returns the letters that the blank tiles stood for in the normalized words, if the target has blanks.
+    pub fn blank_letters<S: AsRef<str>>(&self, normalized: &[S]) -> Option<String> {
+        if self.blanks == 0 {
+            return None;
+        }
+        Some(
+            Self::letters(normalized)
+                .difference(&self.target)
+                .to_string(),
+        )
+    }
+
+    /// Returns the letters the normalized words add to the target and the ones they drop, for near-anagrams.
+    pub fn edits<S: AsRef<str>>(&self, normalized: &[S]) -> Option<(String, String)> {
+        if !self.tolerant {
+            return None;
+        }
+        let letters = Self::letters(normalized);
+        Some((
+            letters.difference(&self.target).to_string(),
+            self.target.difference(&letters).to_string(),
+        ))
+    }
+
+    fn letters<S: AsRef<str>>(normalized: &[S]) -> Signature {
+        let mut letters = Signature::new_empty();
+        for word in normalized {
+            letters.add_saturating(&Signature::new(word.as_ref()));
+        }
+        letters
+    }
+
+    /// Splits an anagram into words and attaches their metadata.
+    /// Words matching the included text are attributed to it, the others to the dictionary.
+    pub fn build<'a>(&'a self, line: &'a str) -> Record<'a> {
+        let mut included: HashMap<&str, usize> = HashMap::new();
+        for word in &self.included_words {
+            *included.entry(word.as_str()).or_default() += 1;
+        }
+
+        let words: Vec<&str> = line.split(' ').collect();
+        let normalized: Vec<String> = words.iter().map(|w| normalize_string(w)).collect();
+        let sources = normalized
+            .iter()
+            .map(|n| match included.get_mut(n.as_str()) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    None
+                }
+                _ => Some(self.dictionary.as_str()),
+            })
+            .collect();
+        let mut length_set: Vec<usize> = normalized.iter().map(|n| n.len()).collect();
+        length_set.sort_unstable();
+
+        let (added, dropped) = self.edits(&normalized).unzip();
+        Record {
+            cardinality: words.len(),
+            leftover: self.leftover(&normalized),
+            blanks: self.blank_letters(&normalized),
+            added,
+            dropped,
+            words,
+            length_set,
+            normalized,
+            sources,
+        }
+    }
+}
+
+/// Writes output lines in one of the stream formats (all but SQLite).
+pub struct RecordWriter<W: Write> {
+    writer: W,
+    format: OutputFormat,
+    header: RunHeader,
+    builder: RecordBuilder,
+    records_number: u64,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Constructor
+    pub fn new(writer: W, format: OutputFormat, header: RunHeader) -> Self {
+        Self {
+            writer,
+            format,
+            builder: RecordBuilder::new(&header),
+            header,
+            records_number: 0,
+        }
+    }
+}
+
+impl<W: Write> OutputSink for RecordWriter<W> {
+    fn write_header(&mut self) -> io::Result<()> {
+        let header = serde_json::to_string(&self.header)?;
+        match self.format {
+            OutputFormat::Text => Ok(()),
+            OutputFormat::Ndjson => writeln!(self.writer, "{{\"run\":{}}}", header),
+            OutputFormat::Json => write!(self.writer, "{{\"run\":{},\"anagrams\":[", header),
+            OutputFormat::Csv => {
+                writeln!(self.writer, "# {}", header)?;
+                let leftover_column = if self.header.options.partial {
+                    ",leftover"
+                } else {
+                    ""
+                };
+                let blanks_column = if self.header.options.blanks > 0 {
+                    ",blanks"
+                } else {
+                    ""
+                };
+                let edits_columns = if self.header.options.tolerance.is_some() {
+                    ",added,dropped"
+                } else {
+                    ""
+                };
+                writeln!(
+                    self.writer,
+                    "words,cardinality,length_set,normalized,sources{}{}{}",
+                    leftover_column, blanks_column, edits_columns
+                )
+            }
+            OutputFormat::Sqlite => unreachable!("SQLite output is written by SqliteWriter"),
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.records_number += 1;
+        if self.format == OutputFormat::Text {
+            let normalized: Vec<String> = line.split(' ').map(normalize_string).collect();
+            write!(self.writer, "{}", line)?;
+            if let Some(leftover) = self.builder.leftover(&normalized) {
+                write!(self.writer, " [{}]", leftover)?;
+            }
+            if let Some(blanks) = self.builder.blank_letters(&normalized) {
+                write!(self.writer, " ({}={})", BLANK, blanks)?;
+            }
+            if let Some((added, dropped)) = self.builder.edits(&normalized) {
+                write!(self.writer, "{}", format_edits(&added, &dropped))?;
+            }
+            return writeln!(self.writer);
+        }
+
+        let record = self.builder.build(line);
+        let row = match self.format {
+            OutputFormat::Text => unreachable!("text lines are written as they are"),
+            OutputFormat::Ndjson | OutputFormat::Json => serde_json::to_string(&record)?,
+            OutputFormat::Csv => csv_row(&record),
+            OutputFormat::Sqlite => unreachable!("SQLite output is written by SqliteWriter"),
+        };
+
+        if self.format == OutputFormat::Json {
+            let separator = if self.records_number == 1 { "" } else { "," };
+            write!(self.writer, "{}\n{}", separator, row)
+        } else {
+            writeln!(self.writer, "{}", row)
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            writeln!(self.writer, "\n]}}")?;
+        }
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Formats a record as a CSV row. Lists are space-separated within their field.
+fn csv_row(record: &Record) -> String {
+    let lengths: Vec<String> = record.length_set.iter().map(|l| l.to_string()).collect();
+    let sources: Vec<&str> = record.sources.iter().map(|s| s.unwrap_or("")).collect();
+
+    let mut row = format!(
+        "{},{},{},{},{}",
+        csv_field(&record.words.join(" ")),
+        record.cardinality,
+        lengths.join(" "),
+        csv_field(&record.normalized.join(" ")),
+        csv_field(&sources.join(" "))
+    );
+    let columns = [
+        &record.leftover,
+        &record.blanks,
+        &record.added,
+        &record.dropped,
+    ];
+    for column in columns.into_iter().flatten() {
+        row.push(',');
+        row.push_str(column);
+    }
+    row
+}
+
+/// Formats the letters a near-anagram adds and drops for the text output, e.g. " (+t -n)". Exact anagrams get nothing.
+fn format_edits(added: &str, dropped: &str) -> String {
+    let mut edits = Vec::new();
+    if !added.is_empty() {
+        edits.push(format!("+{}", added));
+    }
+    if !dropped.is_empty() {
+        edits.push(format!("-{}", dropped));
+    }
+    if edits.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", edits.join(" "))
+    }
+}
+
+/// Quotes a CSV field if needed.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(included_text: &str) -> RunHeader {
+        RunHeader {
+            target: "bazzecole andanti".to_string(),
+            included_text: included_text.to_string(),
+            dictionary: "it.txt".to_string(),
+            dictionary_checksum: "0".to_string(),
+            options: RunOptions {
+                mincard: 1,
+                maxcard: 3,
+                minwlen: 1,
+                maxwlen: 30,
+                output_mode: "Anagrams".to_string(),
+                sorted: false,
+                sample: None,
+                seed: None,
+                pattern: None,
+                partial: false,
+                max_leftover: None,
+                blanks: 0,
+                tolerance: None,
+                letter_bank: false,
+                required_letters: None,
+                max_repeat: None,
+                max_short: None,
+                short_len: None,
+            },
+        }
+    }
+
+    fn write_all(format: OutputFormat, included_text: &str, lines: &[&str]) -> String {
+        write_all_with(format, header(included_text), lines)
+    }
+
+    fn write_all_with(format: OutputFormat, header: RunHeader, lines: &[&str]) -> String {
+        let mut writer = RecordWriter::new(Vec::new(), format, header);
+        writer.write_header().unwrap();
+        for line in lines {
+            writer.write_line(line).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(writer.writer).unwrap()
+    }
+
+    #[test]
+    fn test_record_sources_and_normalization() {
+        let builder = RecordBuilder::new(&header("Città"));
+        let record = builder.build("andanti Città zelo");
+
+        assert_eq!(record.cardinality, 3);
+        assert_eq!(record.normalized, vec!["andanti", "citta", "zelo"]);
+        assert_eq!(record.length_set, vec![4, 5, 7]);
+        assert_eq!(record.sources, vec![Some("it.txt"), None, Some("it.txt")]);
+    }
+
+    #[test]
+    fn test_ndjson_and_json_are_valid() {
+        let ndjson = write_all(OutputFormat::Ndjson, "", &["a b", "c"]);
+        let values: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0]["run"]["dictionary"], "it.txt");
+        assert_eq!(values[1]["words"], serde_json::json!(["a", "b"]));
+
+        let json = write_all(OutputFormat::Json, "", &["a b", "c"]);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["anagrams"].as_array().unwrap().len(), 2);
+        assert_eq!(value["anagrams"][1]["cardinality"], 1);
+
+        let empty = write_all(OutputFormat::Json, "", &[]);
+        let value: serde_json::Value = serde_json::from_str(&empty).unwrap();
+        assert!(value["anagrams"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_csv_and_text() {
+        let csv = write_all(OutputFormat::Csv, "", &["l'acqua b"]);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("# {"));
+        assert_eq!(lines[2], "l'acqua b,2,1 6,lacqua b,it.txt it.txt");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+
+        assert_eq!(write_all(OutputFormat::Text, "", &["a b", "c"]), "a b\nc\n");
+    }
+
+    #[test]
+    fn test_partial_leftover() {
+        let mut header = header("");
+        header.options.partial = true;
+
+        let text = write_all_with(
+            OutputFormat::Text,
+            header.clone(),
+            &["andanti", "bazzecole andanti"],
+        );
+        assert_eq!(text, "andanti [abceelozz]\nbazzecole andanti []\n");
+
+        let csv = write_all_with(OutputFormat::Csv, header.clone(), &["andanti"]);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[1].ends_with(",leftover"));
+        assert!(lines[2].ends_with(",abceelozz"));
+
+        let ndjson = write_all_with(OutputFormat::Ndjson, header, &["andanti"]);
+        let record: serde_json::Value =
+            serde_json::from_str(ndjson.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(record["leftover"], "abceelozz");
+
+        // Exact searches have no leftover field
+        let ndjson = write_all(OutputFormat::Ndjson, "", &["andanti"]);
+        assert!(!ndjson.lines().nth(1).unwrap().contains("leftover"));
+    }
+
+    #[test]
+    fn test_blank_letters() {
+        let mut header = header("");
+        header.target = "bazzecole andan??".to_string();
+        header.options.blanks = 2;
+
+        let text = write_all_with(OutputFormat::Text, header.clone(), &["bazzecole andanti"]);
+        assert_eq!(text, "bazzecole andanti (?=it)\n");
+
+        let csv = write_all_with(OutputFormat::Csv, header.clone(), &["bazzecole andanti"]);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[1].ends_with("sources,blanks"));
+        assert!(lines[2].ends_with(",it"));
+
+        // In partial mode, the unused blanks are left over
+        header.options.partial = true;
+        let text = write_all_with(OutputFormat::Text, header, &["bazzecole andant"]);
+        assert_eq!(text, "bazzecole andant [?] (?=t)\n");
+    }
+
+    #[test]
+    fn test_near_anagram_edits() {
+        let mut header = header("");
+        header.options.tolerance = Some(Tolerance { add: 1, drop: 1 });
+
+        let text = write_all_with(
+            OutputFormat::Text,
+            header.clone(),
+            &["bazzecole andanti", "bazzecole andante", "bazzecole andant"],
+        );
+        assert_eq!(
+            text,
+            "bazzecole andanti\nbazzecole andante (+e -i)\nbazzecole andant (-i)\n"
+        );
+
+        let csv = write_all_with(OutputFormat::Csv, header.clone(), &["bazzecole andante"]);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[1].ends_with("sources,added,dropped"));
+        assert!(lines[2].ends_with(",e,i"));
+
+        let ndjson = write_all_with(OutputFormat::Ndjson, header, &["bazzecole andanti"]);
+        let record: serde_json::Value =
+            serde_json::from_str(ndjson.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(record["added"], "");
+        assert_eq!(record["dropped"], "");
+    }
+}
@@ -1,22 +1,89 @@
 mod armamagna;
 mod combinations;
+mod compact;
+mod console;
 mod dictionarium;
+mod filter;
+mod format;
+mod manifest;
+mod memo;
+mod pattern;
+mod progress;
+mod sampling;
+mod scheduler;
 mod search;
 mod signature;
+mod sorter;
+mod sqlite;
+mod summary;
 
 use std::error::Error;
 use std::thread;
 
 use armamagna::ArmaMagna;
+use dictionarium::count_blanks;
+use filter::{WordFilter, WordList};
+use format::OutputFormat;
+use manifest::Manifest;
+use progress::ProgressFormat;
+use search::{OutputMode, Tolerance, WordLimits};
 
 use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+/// Cardinality bounds of a search that doesn't set them.
+const DEFAULT_MINCARD: u64 = 1;
+const DEFAULT_MAXCARD: u64 = 3;
 
 #[derive(clap::Parser, Debug)]
 #[command(author = "Gabriele Cassetta, @merhametsize", version, about = "ArmaMagna", long_about = None)]
 #[command(
     after_help = "Example:\n  ./armamagna \"bazzecole andanti\" -d ../../data/it.txt --mincard 1 --maxcard 3"
 )]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    search: Option<SearchArgs>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Expand a file written with --compact into one anagram per line
+    Expand {
+        /// Compact file to expand
+        input_file: String,
+
+        /// Output file
+        #[arg(short = 'o', long = "out", default_value = "anagrams.txt")]
+        output_file: String,
+
+        /// Keep the words in the order of the groups, for a file written with --pattern
+        #[arg(long)]
+        ordered: bool,
+    },
+
+    /// Run again the search described by a manifest (written next to the output as <output>.manifest.json)
+    Rerun {
+        /// Manifest of the search to run again
+        manifest_file: String,
+
+        /// Output file, instead of the one of the manifest
+        #[arg(short = 'o', long = "out")]
+        output_file: Option<String>,
+
+        /// Number of threads, instead of the one of the manifest
+        #[arg(short = 't', long = "thr")]
+        num_threads: Option<usize>,
+    },
+}
+
+/// Options of a search. Recorded in the run manifest, so options added later need #[serde(default)] to keep
+/// older manifests readable.
+#[derive(clap::Args, Debug, Serialize, Deserialize)]
+struct SearchArgs {
     /// Text to anagram
     text: String,
 
@@ -28,13 +95,13 @@ struct Args {
     #[arg(short = 'i', long = "incl", default_value = "")]
     included_text: String,
 
-    /// Minimum cardinality (number of words in the anagram)
-    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value="1")]
-    mincard: u64,
+    /// Minimum cardinality (number of words in the anagram) [default: 1]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    mincard: Option<u64>,
 
-    /// Maximum cardinality (number of words in the anagram)
-    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value="3")]
-    maxcard: u64,
+    /// Maximum cardinality (number of words in the anagram) [default: 3, or the words of the pattern]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    maxcard: Option<u64>,
 
     /// Minimum word length
     #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value="1")]
@@ -44,10 +111,149 @@ struct Args {
     #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value="30")]
     maxwlen: u64,
 
-    /// Output file
+    /// Shape of the anagrams, words in order, '?' for an unknown letter: "?a??? ?????e" (sets the cardinality)
+    #[arg(long, conflicts_with = "included_text")]
+    #[serde(default)]
+    pattern: Option<String>,
+
+    /// Exact word lengths of the anagrams, in output order: 5,3,7 (sets the cardinality)
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["pattern", "included_text"], value_parser = clap::value_parser!(u64).range(1..))]
+    #[serde(default)]
+    lengths: Vec<u64>,
+
+    /// Also find the word combinations that use only some of the letters, each one with its unused letters
+    #[arg(long, conflicts_with_all = ["pattern", "lengths", "compact"])]
+    #[serde(default)]
+    partial: bool,
+
+    /// Maximum number of unused letters in partial mode
+    #[arg(long, value_name = "N", requires = "partial")]
+    #[serde(default)]
+    max_leftover: Option<u64>,
+
+    /// Also find near-anagrams, adding and dropping up to this many letters, e.g. add=1,drop=1
+    #[arg(long, value_name = "add=N,drop=N", value_parser = Tolerance::parse, conflicts_with_all = ["pattern", "lengths", "partial", "compact"])]
+    #[serde(default)]
+    tolerance: Option<Tolerance>,
+
+    /// Treat the text as a bank of letters that words may reuse freely, as in spelling games
+    #[arg(long, conflicts_with_all = ["pattern", "lengths", "partial", "tolerance", "included_text"])]
+    #[serde(default)]
+    letter_bank: bool,
+
+    /// Letters that every phrase must contain in letter bank mode, e.g. the centre letter of a spelling bee
+    #[arg(long, value_name = "LETTERS", requires = "letter_bank")]
+    #[serde(default)]
+    require_letter: Option<String>,
+
+    /// Never use the same word twice in an anagram (same as --max-repeat 1)
+    #[arg(long, conflicts_with_all = ["max_repeat", "pattern", "lengths", "compact"])]
+    #[serde(default)]
+    distinct_words: bool,
+
+    /// Maximum number of times the same word may occur in an anagram
+    #[arg(long, value_name = "N", conflicts_with_all = ["pattern", "lengths", "compact"], value_parser = clap::value_parser!(u64).range(1..))]
+    #[serde(default)]
+    max_repeat: Option<u64>,
+
+    /// Maximum number of short words in an anagram, see --short-len
+    #[arg(long, value_name = "N", requires = "short_len")]
+    #[serde(default)]
+    max_short: Option<u64>,
+
+    /// Length up to which a word counts as short for --max-short
+    #[arg(long, value_name = "LEN", requires = "max_short", value_parser = clap::value_parser!(u64).range(1..))]
+    #[serde(default)]
+    short_len: Option<u64>,
+
+    /// Exclude a word from the anagrams, compared on its normalized form (repeatable)
+    #[arg(long = "exclude-word", value_name = "WORD")]
+    #[serde(default)]
+    exclude_words: Vec<String>,
+
+    /// Exclude the words listed in a file, one per line
+    #[arg(long = "exclude-words", value_name = "FILE")]
+    #[serde(default)]
+    exclude_words_file: Option<String>,
+
+    /// Exclude the words matching a regular expression, e.g. "^[A-Z]" for names (repeatable)
+    #[arg(long = "exclude-pattern", value_name = "REGEX")]
+    #[serde(default)]
+    exclude_patterns: Vec<String>,
+
+    /// Exclude the words of the text to anagram, so that it doesn't come back as its own anagram
+    #[arg(long)]
+    #[serde(default)]
+    no_source_words: bool,
+
+    /// Exclude the words sharing their first LEN letters (default 5) with a word of the text to anagram
+    #[arg(long, value_name = "LEN", num_args = 0..=1, default_missing_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    #[serde(default)]
+    no_source_stems: Option<u64>,
+
+    /// Remove the words listed in this file (one per line) from the dictionary and the included text
+    #[arg(long, value_name = "FILE")]
+    #[serde(default)]
+    blocklist: Option<String>,
+
+    /// Restrict the dictionary and the included text to the words listed in this file (one per line)
+    #[arg(long, value_name = "FILE")]
+    #[serde(default)]
+    allowlist: Option<String>,
+
+    /// Match the blocklist and allowlist exactly as written, instead of on the normalized form
+    #[arg(long)]
+    #[serde(default)]
+    case_exact: bool,
+
+    /// Output file, "-" for stdout
     #[arg(short = 'o', long = "out", default_value = "anagrams.txt")]
     output_file: String,
 
+    /// Maximum number of sub-solutions kept in the memo cache (0 disables it)
+    #[arg(long = "memo", default_value_t = 1 << 20)]
+    memo_size: usize,
+
+    /// Only count the anagrams (by cardinality and length combination), without writing them
+    #[arg(long, conflicts_with = "compact")]
+    count_only: bool,
+
+    /// Write one line per signature solution, grouping alternative words: {bar|bra} {man|nam}
+    #[arg(long)]
+    compact: bool,
+
+    /// Write only a uniform random sample of n anagrams
+    #[arg(long, value_name = "N", conflicts_with_all = ["count_only", "compact"])]
+    sample: Option<u64>,
+
+    /// Seed of the random sample: the same seed and dictionary always give the same anagrams
+    #[arg(long, default_value_t = 0, requires = "sample")]
+    seed: u64,
+
+    /// Write the output in a deterministic order: by cardinality, then length combination, then words
+    #[arg(long)]
+    sorted: bool,
+
+    /// Output file format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Also write the run summary (dictionary sizes, search statistics, timings) as JSON to this file
+    #[arg(long, value_name = "PATH")]
+    summary_json: Option<String>,
+
+    /// Progress report format: a status line, or JSON events on stderr
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Text)]
+    progress: ProgressFormat,
+
+    /// Only print errors (and JSON progress events, if requested)
+    #[arg(short = 'q', long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print more details (-v), and debugging information (-vv)
+    #[arg(short = 'v', long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
     /// Number of threads
     #[arg(short = 't', long = "thr", default_value_t = thread::available_parallelism().map(|n| n.get()).unwrap_or(1))]
     num_threads: usize,
@@ -57,6 +263,125 @@ fn main() -> Result<(), Box<dyn Error>> {
     // Command line parsing
     let args = Args::parse();
 
+    match (args.command, args.search) {
+        (
+            Some(Command::Expand {
+                input_file,
+                output_file,
+                ordered,
+            }),
+            _,
+        ) => {
+            let anagrams_written = compact::expand_file(&input_file, &output_file, ordered)?;
+            println!(
+                "Expanded {} anagrams. Output written to {}.",
+                anagrams_written, output_file
+            );
+            Ok(())
+        }
+        (
+            Some(Command::Rerun {
+                manifest_file,
+                output_file,
+                num_threads,
+            }),
+            _,
+        ) => rerun(&manifest_file, output_file, num_threads),
+        (None, Some(search_args)) => search(search_args, None),
+        (None, None) => unreachable!("clap requires the search arguments without a subcommand"),
+    }
+}
+
+/// Builds the filter of the excluded words.
+fn word_filter(args: &SearchArgs) -> Result<WordFilter, String> {
+    let mut filter = WordFilter::new();
+    for word in &args.exclude_words {
+        filter.add_word(word);
+    }
+    if let Some(path) = &args.exclude_words_file {
+        filter.add_words_file(path)?;
+    }
+    for pattern in &args.exclude_patterns {
+        filter.add_pattern(pattern)?;
+    }
+    for word in args.text.split_whitespace() {
+        if args.no_source_words {
+            filter.add_word(word);
+        }
+        if let Some(stem_length) = args.no_source_stems {
+            filter.add_stem(word, stem_length as usize);
+        }
+    }
+    Ok(filter)
+}
+
+/// Runs the search of a manifest again, on the same dictionary.
+fn rerun(
+    manifest_file: &str,
+    output_file: Option<String>,
+    num_threads: Option<usize>,
+) -> Result<(), Box<dyn Error>> {
+    let manifest = Manifest::read(manifest_file)?;
+    let mut args = manifest.args;
+    if let Some(output_file) = output_file {
+        args.output_file = output_file;
+    }
+    if let Some(num_threads) = num_threads {
+        args.num_threads = num_threads;
+    }
+
+    if manifest.version != env!("CARGO_PKG_VERSION") {
+        eprintln!(
+            "Warning: the manifest was written by version {}, this is version {}",
+            manifest.version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+
+    search(args, Some(&manifest.dictionary_checksum))
+}
+
+/// Checks the output options that clap can't check on its own.
+fn check_output_args(args: &SearchArgs) -> Result<(), String> {
+    if args.compact && args.format != OutputFormat::Text {
+        return Err("Compact output is only available in the text format".to_string());
+    }
+    if args.compact && count_blanks(&args.text) > 0 {
+        return Err(
+            "Compact output can't show the letters of the blank tiles, drop --compact".to_string(),
+        );
+    }
+    if args.format == OutputFormat::Sqlite && args.output_file == "-" {
+        return Err(
+            "SQLite output needs a database file, it can't be written to stdout".to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Runs an anagram search. If a dictionary checksum is expected, the search fails on any other dictionary.
+fn search(args: SearchArgs, expected_checksum: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let output_mode = if args.count_only {
+        OutputMode::CountOnly
+    } else if args.compact {
+        OutputMode::Compact
+    } else {
+        OutputMode::Anagrams
+    };
+
+    check_output_args(&args)?;
+
+    // When the anagrams go to stdout, everything else goes to stderr
+    console::set_stderr_mode(args.output_file == "-");
+    console::set_verbosity(if args.quiet { 0 } else { 1 + args.verbose });
+
+    // A pattern gives the number of words itself: the cardinality bounds only restrict it if they are set
+    let shaped = args.pattern.is_some() || !args.lengths.is_empty();
+    let mincard = args.mincard.unwrap_or(DEFAULT_MINCARD);
+    let maxcard = args
+        .maxcard
+        .unwrap_or(if shaped { u64::MAX } else { DEFAULT_MAXCARD });
+
     // Initialize ArmaMagna
     let mut am = ArmaMagna::new();
     am.set_options(
@@ -64,20 +389,137 @@ fn main() -> Result<(), Box<dyn Error>> {
         &args.dictionary,
         &args.output_file,
         &args.included_text,
-        args.mincard,
-        args.maxcard,
+        mincard,
+        maxcard,
         args.minwlen,
         args.maxwlen,
         args.num_threads as u64,
     )?;
+    if let Some(pattern) = &args.pattern {
+        am.set_pattern(pattern)?;
+    }
+    if args.partial {
+        am.set_partial(args.max_leftover)?;
+    }
+    if !args.lengths.is_empty() {
+        let lengths: Vec<usize> = args.lengths.iter().map(|&l| l as usize).collect();
+        am.set_lengths(&lengths)?;
+    }
+    if let Some(tolerance) = args.tolerance {
+        am.set_tolerance(tolerance)?;
+    }
+    if args.letter_bank {
+        am.set_letter_bank(args.require_letter.as_deref().unwrap_or(""))?;
+    }
+    am.set_word_limits(WordLimits {
+        max_repeat: if args.distinct_words {
+            Some(1)
+        } else {
+            args.max_repeat
+        },
+        max_short: args.max_short.zip(args.short_len.map(|l| l as usize)),
+    })?;
+    am.set_word_filter(word_filter(&args)?);
+    am.set_word_lists(
+        args.blocklist
+            .as_deref()
+            .map(|path| WordList::read(path, args.case_exact))
+            .transpose()?,
+        args.allowlist
+            .as_deref()
+            .map(|path| WordList::read(path, args.case_exact))
+            .transpose()?,
+    );
+    am.set_memo_size(args.memo_size);
+    am.set_output_mode(output_mode);
+    am.set_sorted(args.sorted);
+    am.set_output_format(args.format);
+    am.set_progress_format(args.progress);
+    if let Some(path) = &args.summary_json {
+        am.set_summary_file(path);
+    }
+    if let Some(n) = args.sample {
+        am.set_sample(n, args.seed);
+    }
+    if let Some(checksum) = expected_checksum {
+        am.set_expected_checksum(checksum);
+    }
 
     // Run the search
     let anagrams_found = am.anagram()?;
-    println!(
-        "\nFound {} anagrams. Output written to {}.",
-        anagrams_found, args.output_file
-    );
+    if am.get_summary().output_truncated {
+        return Ok(()); // The reader closed the pipe: exit quietly, like other Unix tools
+    }
+    let destination = if args.output_file == "-" {
+        "stdout"
+    } else {
+        args.output_file.as_str()
+    };
+    match output_mode {
+        OutputMode::CountOnly => result!("\nCounted {} anagrams.", anagrams_found),
+        OutputMode::Compact => status!(
+            "\nFound {} anagrams. Compact output written to {} (see the expand command).",
+            anagrams_found,
+            destination
+        ),
+        OutputMode::Anagrams => status!(
+            "\nFound {} anagrams. Output written to {}.",
+            anagrams_found,
+            destination
+        ),
+    }
+
+    // The manifest goes next to the output file
+    if output_mode != OutputMode::CountOnly && args.output_file != "-" {
+        let summary = am.get_summary();
+        let manifest_file = Manifest::path_for(&args.output_file);
+        let manifest = Manifest::new(
+            args,
+            &summary.dictionary_checksum,
+            anagrams_found,
+            summary.timings.clone(),
+        );
+        manifest.write(&manifest_file)?;
+        status!("Manifest written to {}.", manifest_file);
+    }
 
     // Success return
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(extra: &[&str]) -> Result<Args, clap::Error> {
+        let mut argv = vec!["armamagna", "barman", "-d", "dict.txt"];
+        argv.extend_from_slice(extra);
+        Args::try_parse_from(argv)
+    }
+
+    #[test]
+    fn test_partial_conflicts_with_compact() {
+        assert!(parse(&["--partial"]).is_ok());
+        assert!(parse(&["--compact"]).is_ok());
+        assert!(parse(&["--partial", "--compact"]).is_err());
+    }
+
+    #[test]
+    fn test_tolerance_conflicts_with_compact() {
+        assert!(parse(&["--tolerance", "add=1"]).is_ok());
+        assert!(parse(&["--tolerance", "add=1", "--compact"]).is_err());
+    }
+
+    fn search_args(text: &str, extra: &[&str]) -> SearchArgs {
+        let mut argv = vec!["armamagna", text, "-d", "dict.txt"];
+        argv.extend_from_slice(extra);
+        Args::try_parse_from(argv).unwrap().search.unwrap()
+    }
+
+    #[test]
+    fn test_compact_rejects_blanks() {
+        assert!(check_output_args(&search_args("barm??", &[])).is_ok());
+        assert!(check_output_args(&search_args("barman", &["--compact"])).is_ok());
+        assert!(check_output_args(&search_args("barm??", &["--compact"])).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::SearchArgs;
+use crate::dictionarium::NORMALIZATION_PROFILE;
+use crate::summary::PhaseTimings;
+
+/// Describes a finished search well enough to reproduce it: written next to the output as
+/// `<output>.manifest.json`, read back by the rerun command.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub version: String,
+    pub normalization: String,       // NORMALIZATION_PROFILE
+    pub dictionary_checksum: String, // Dictionarium::get_checksum(), hexadecimal
+    pub args: SearchArgs, // Target text, included text, dictionary path, bounds, threads...
+    pub anagrams_found: u64,
+    pub timings: PhaseTimings,
+}
+
+impl Manifest {
+    /// Constructor, for a search run by this version of ArmaMagna.
+    pub fn new(
+        args: SearchArgs,
+        dictionary_checksum: &str,
+        anagrams_found: u64,
+        timings: PhaseTimings,
+    ) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            normalization: NORMALIZATION_PROFILE.to_string(),
+            dictionary_checksum: dictionary_checksum.to_string(),
+            args,
+            anagrams_found,
+            timings,
+        }
+    }
+
+    /// Returns the manifest path of an output file.
+    pub fn path_for(output_file: &str) -> String {
+        format!("{}.manifest.json", output_file)
+    }
+
+    /// Writes the manifest as JSON.
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, json + "\n").map_err(|e| format!("Cannot write file {}: {}", path, e))
+    }
+
+    /// Reads a manifest. Fails if it was written with another normalization, which would give other results.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let json =
+            fs::read_to_string(path).map_err(|e| format!("Cannot read file {}: {}", path, e))?;
+        let manifest: Self =
+            serde_json::from_str(&json).map_err(|e| format!("Invalid manifest {}: {}", path, e))?;
+
+        if manifest.normalization != NORMALIZATION_PROFILE {
+            return Err(format!(
+                "Manifest {} uses the {} normalization, this version uses {}",
+                path, manifest.normalization, NORMALIZATION_PROFILE
+            ));
+        }
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+    use tempfile::NamedTempFile;
+
+    fn search_args(argv: &[&str]) -> SearchArgs {
+        Args::try_parse_from(argv).unwrap().search.unwrap()
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let args = search_args(&[
+            "armamagna",
+            "barman zelo",
+            "-d",
+            "it.txt",
+            "-i",
+            "zelo",
+            "--maxcard",
+            "4",
+            "-t",
+            "2",
+            "--sorted",
+        ]);
+        let manifest = Manifest::new(args, "00000000deadbeef", 42, PhaseTimings::default());
+
+        let tmp_file = NamedTempFile::new().unwrap();
+        let path = tmp_file.path().to_str().unwrap();
+        manifest.write(path).unwrap();
+        let read = Manifest::read(path).unwrap();
+
+        assert_eq!(read.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(read.dictionary_checksum, "00000000deadbeef");
+        assert_eq!(read.anagrams_found, 42);
+        assert_eq!(read.args.text, "barman zelo");
+        assert_eq!(read.args.included_text, "zelo");
+        assert_eq!(read.args.maxcard, Some(4));
+        assert_eq!(read.args.num_threads, 2);
+        assert!(read.args.sorted);
+    }
+
+    #[test]
+    fn test_other_normalization_is_rejected() {
+        let mut manifest = Manifest::new(
+            search_args(&["armamagna", "barman", "-d", "it.txt"]),
+            "0",
+            0,
+            PhaseTimings::default(),
+        );
+        manifest.normalization = "nfc".to_string();
+
+        let tmp_file = NamedTempFile::new().unwrap();
+        let path = tmp_file.path().to_str().unwrap();
+        manifest.write(path).unwrap();
+
+        assert!(Manifest::read(path).unwrap_err().contains("normalization"));
+    }
+}
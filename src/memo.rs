@@ -0,0 +1,186 @@
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use fxhash::{FxHashMap, FxHasher};
+use serde::Serialize;
+
+use crate::signature::Signature;
+
+/// Maximum number of completions stored in a single entry. Sub-problems with more completions are never cached,
+/// they are cheap to re-explore compared to the output they generate anyway.
+pub const MAX_COMPLETIONS: usize = 64;
+
+/// Number of independently locked shards, to keep contention between search threads low.
+const SHARDS_NUMBER: usize = 64;
+
+/// Word lengths are packed in 6 bits each (lengths are always <= MAX_WORD_LENGTH < 64).
+const LENGTH_BITS: usize = 6;
+const MAX_SUFFIX_WORDS: usize = 128 / LENGTH_BITS;
+
+/// Identifies a search sub-problem: the letters still to be placed, the lengths of the words still to be chosen and
+/// the lowest signature allowed in its first slot (empty if any signature is).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct MemoKey {
+    remaining: Signature,
+    lengths: u128,
+    lower_bound: Signature,
+}
+
+impl MemoKey {
+    /// Builds a key, or returns None if the length suffix is too long to be packed.
+    pub fn new(
+        remaining: &Signature,
+        lengths: &[usize],
+        lower_bound: Option<Signature>,
+    ) -> Option<Self> {
+        if lengths.len() > MAX_SUFFIX_WORDS {
+            return None;
+        }
+
+        let mut packed = 0u128;
+        for &len in lengths {
+            debug_assert!(len > 0 && len < (1 << LENGTH_BITS));
+            packed = (packed << LENGTH_BITS) | len as u128;
+        }
+
+        Some(Self {
+            remaining: *remaining,
+            lengths: packed,
+            lower_bound: lower_bound.unwrap_or_else(Signature::new_empty),
+        })
+    }
+}
+
+/// Snapshot of the cache counters.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct MemoStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u64,
+}
+
+impl MemoStats {
+    /// Returns the hit rate in percent.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            100.0 * self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// Bounded memo cache shared by all search threads. Maps a sub-problem to the flat list of its signature completions,
+/// each completion being `suffix length` signatures long. An empty list means the sub-problem has no solution.
+/// When full, new entries are simply dropped: nothing is ever evicted.
+pub struct MemoCache {
+    shards: Vec<Mutex<FxHashMap<MemoKey, Arc<[Signature]>>>>,
+    shard_capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl MemoCache {
+    /// Constructor. A capacity of 0 disables the cache.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            shards: (0..SHARDS_NUMBER)
+                .map(|_| Mutex::new(FxHashMap::default()))
+                .collect(),
+            shard_capacity: capacity.div_ceil(SHARDS_NUMBER),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns true if the cache may hold entries.
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.shard_capacity > 0
+    }
+
+    /// Looks up a sub-problem, updating the hit/miss counters.
+    pub fn get(&self, key: &MemoKey) -> Option<Arc<[Signature]>> {
+        let shard = self.shards[Self::shard_index(key)].lock().unwrap();
+        let result = shard.get(key).cloned();
+
+        if result.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Stores the completions of a sub-problem, unless its shard is full.
+    pub fn insert(&self, key: MemoKey, completions: Vec<Signature>) {
+        let mut shard = self.shards[Self::shard_index(&key)].lock().unwrap();
+        if shard.len() < self.shard_capacity {
+            shard.insert(key, completions.into());
+        }
+    }
+
+    /// Returns the current counters.
+    pub fn get_stats(&self) -> MemoStats {
+        MemoStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self
+                .shards
+                .iter()
+                .map(|s| s.lock().unwrap().len() as u64)
+                .sum(),
+        }
+    }
+
+    fn shard_index(key: &MemoKey) -> usize {
+        let mut hasher = FxHasher::default();
+        key.hash(&mut hasher);
+        (hasher.finish() as usize) % SHARDS_NUMBER
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_depends_on_lengths_order() {
+        let sig = Signature::new("abc");
+        let k1 = MemoKey::new(&sig, &[1, 2], None).unwrap();
+        let k2 = MemoKey::new(&sig, &[2, 1], None).unwrap();
+        assert_ne!(k1, k2);
+        assert_ne!(
+            k1,
+            MemoKey::new(&sig, &[1, 2], Some(Signature::new("a"))).unwrap()
+        );
+        assert!(MemoKey::new(&sig, &[1; MAX_SUFFIX_WORDS + 1], None).is_none());
+    }
+
+    #[test]
+    fn test_insert_get_and_stats() {
+        let cache = MemoCache::new(128);
+        let key = MemoKey::new(&Signature::new("ab"), &[1, 1], None).unwrap();
+
+        assert!(cache.get(&key).is_none());
+        cache.insert(key, vec![Signature::new("a"), Signature::new("b")]);
+        let completions = cache.get(&key).unwrap();
+        assert_eq!(completions.len(), 2);
+
+        let stats = cache.get_stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+        assert_eq!(stats.hit_rate(), 50.0);
+    }
+
+    #[test]
+    fn test_disabled_cache_stores_nothing() {
+        let cache = MemoCache::new(0);
+        assert!(!cache.is_enabled());
+
+        let key = MemoKey::new(&Signature::new("a"), &[1], None).unwrap();
+        cache.insert(key, Vec::new());
+        assert_eq!(cache.get_stats().entries, 0);
+    }
+}
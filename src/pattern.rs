@@ -0,0 +1,152 @@
+use crate::dictionarium::{Dictionarium, Section, normalize_string};
+use crate::signature::Signature;
+
+/// Placeholder of an unknown letter in a pattern.
+pub const WILDCARD: char = '?';
+
+/// The shape of an answer, one slot per word in output order, e.g. `?a??? ?????e`: each slot gives the length of
+/// its word and the letters known at some positions. Words are not interchangeable between slots, so a pattern
+/// anagram keeps the slot order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WordPattern {
+    slots: Vec<Vec<Option<u8>>>, // Known normalized letter per position
+}
+
+impl WordPattern {
+    /// Parses a pattern: words separated by spaces, '?' for an unknown letter.
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let mut slots = Vec::new();
+        for word in pattern.split_whitespace() {
+            let mut slot = Vec::new();
+            for c in word.chars() {
+                if c == WILDCARD {
+                    slot.push(None);
+                    continue;
+                }
+                match normalize_string(&c.to_string()).as_bytes() {
+                    [letter] if letter.is_ascii_lowercase() => slot.push(Some(*letter)),
+                    _ => return Err(format!("Invalid character '{}' in pattern {}", c, pattern)),
+                }
+            }
+            slots.push(slot);
+        }
+
+        if slots.is_empty() {
+            return Err("The pattern is empty".to_string());
+        }
+        Ok(Self { slots })
+    }
+
+    /// Builds a pattern of unknown letters from word lengths, in output order.
+    pub fn from_lengths(lengths: &[usize]) -> Self {
+        Self {
+            slots: lengths.iter().map(|&len| vec![None; len]).collect(),
+        }
+    }
+
+    /// Returns the word length of every slot.
+    pub fn get_lengths(&self) -> Vec<usize> {
+        self.slots.iter().map(|s| s.len()).collect()
+    }
+
+    /// Returns the slot-th slot as written in a pattern, e.g. "?a???".
+    pub fn get_slot_text(&self, slot: usize) -> String {
+        self.slots[slot]
+            .iter()
+            .map(|known| known.map_or(WILDCARD, |l| l as char))
+            .collect()
+    }
+
+    /// Returns the signature of the known letters, which must all come from the text to anagram.
+    pub fn get_known_letters(&self) -> Result<Signature, String> {
+        let known: String = self
+            .slots
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|&l| l as char)
+            .collect();
+        Signature::try_new(&known)
+    }
+
+    /// Returns true if a normalized word fits the slot-th slot.
+    pub fn matches(&self, slot: usize, normalized_word: &str) -> bool {
+        let pattern = &self.slots[slot];
+        normalized_word.len() == pattern.len()
+            && pattern
+                .iter()
+                .zip(normalized_word.bytes())
+                .all(|(known, letter)| known.is_none_or(|k| k == letter))
+    }
+
+    /// Builds one section per slot out of the dictionary, holding only the words that fit the slot.
+    pub fn slot_sections(&self, dictionarium: &Dictionarium) -> Vec<Section> {
+        (0..self.slots.len())
+            .map(|slot| {
+                let mut section = Section::default();
+                for (ws, words) in dictionarium.get_section(self.slots[slot].len()) {
+                    let fitting: Vec<String> = words
+                        .iter()
+                        .filter(|w| self.matches(slot, &normalize_string(w)))
+                        .cloned()
+                        .collect();
+                    if !fitting.is_empty() {
+                        section.insert(*ws, fitting);
+                    }
+                }
+                section
+            })
+            .collect()
+    }
+}
+
+/// Formats a pattern back, for the banner.
+impl std::fmt::Display for WordPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let words: Vec<String> = (0..self.slots.len())
+            .map(|slot| self.get_slot_text(slot))
+            .collect();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_parse_and_match() {
+        let pattern = WordPattern::parse("?a??? ?????È").unwrap();
+        assert_eq!(pattern.get_lengths(), vec![5, 6]);
+        assert_eq!(pattern.to_string(), "?a??? ?????e");
+        assert_eq!(pattern.get_slot_text(1), "?????e");
+        assert_eq!(pattern.get_known_letters(), Ok(Signature::new("ae")));
+
+        assert!(pattern.matches(0, "barca"));
+        assert!(!pattern.matches(0, "borca"));
+        assert!(!pattern.matches(0, "barcaa"));
+        assert!(pattern.matches(1, "andate"));
+
+        assert_eq!(WordPattern::from_lengths(&[3, 1]).to_string(), "??? ?");
+        assert!(WordPattern::parse("a-b").is_err());
+        assert!(WordPattern::parse("  ").is_err());
+    }
+
+    #[test]
+    fn test_slot_sections() {
+        let mut tmp_file = NamedTempFile::new().unwrap();
+        for word in ["bar", "bra", "rab", "man"] {
+            writeln!(tmp_file, "{}", word).unwrap();
+        }
+        let mut dict = Dictionarium::new();
+        dict.read_word_list(tmp_file.path().to_str().unwrap(), "barman")
+            .unwrap();
+
+        let sections = WordPattern::parse("b?? ??b").unwrap().slot_sections(&dict);
+        assert_eq!(sections[0][&Signature::new("abr")], vec!["bar", "bra"]);
+        assert!(!sections[0].contains_key(&Signature::new("amn")));
+        assert_eq!(sections[1][&Signature::new("abr")], vec!["rab"]);
+    }
+}
@@ -0,0 +1,202 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::console;
+
+/// Time between two progress reports.
+const REPORT_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// How progress is reported during the search.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressFormat {
+    Text, // A status line rewritten in place, hidden by --quiet
+    Json, // One JSON event per line on stderr, for programs
+}
+
+/// A progress report in the JSON format.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ProgressEvent {
+    pub event: &'static str, // "progress" while searching, "done" at the end
+    pub sets_explored: u64,
+    pub sets_total: u64,
+    pub anagrams_found: u64,
+    pub completed_fraction: f64, // Estimated cost of the finished tasks over the total one
+    pub elapsed_seconds: f64,
+    pub anagrams_per_second: f64,
+    pub eta_seconds: Option<f64>, // Unknown until some task is finished
+}
+
+/// Adds the estimated cost of a finished task to the completed cost, stored as f64 bits.
+pub fn add_completed_cost(completed_cost: &AtomicU64, cost: f64) {
+    let _ = completed_cost.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+        Some((f64::from_bits(bits) + cost).to_bits())
+    });
+}
+
+/// Reports the progress of the search at regular intervals. Driven by the IO thread.
+/// Length combinations differ in cost by orders of magnitude, so the ETA is based on the estimated cost of the
+/// finished tasks (see scheduler::CostModel), not on the number of explored combinations.
+pub struct ProgressReporter {
+    format: ProgressFormat,
+    explored_sets: Arc<AtomicU64>,
+    sets_number: u64,
+    completed_cost: Arc<AtomicU64>, // f64 bits, see add_completed_cost()
+    total_cost: f64,
+    start: Instant,
+    last_report: Instant,
+}
+
+impl ProgressReporter {
+    /// Constructor. The clock starts now.
+    pub fn new(
+        format: ProgressFormat,
+        explored_sets: Arc<AtomicU64>,
+        sets_number: u64,
+        completed_cost: Arc<AtomicU64>,
+        total_cost: f64,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            format,
+            explored_sets,
+            sets_number,
+            completed_cost,
+            total_cost,
+            start: now,
+            last_report: now,
+        }
+    }
+
+    /// Returns how long to wait before the next report is due.
+    pub fn time_to_next_report(&self) -> Duration {
+        REPORT_INTERVAL.saturating_sub(self.last_report.elapsed())
+    }
+
+    /// Returns true if a report is due.
+    pub fn is_due(&self) -> bool {
+        self.time_to_next_report().is_zero()
+    }
+
+    /// Reports the progress. last_anagram is shown by the text format, if any.
+    pub fn report(&mut self, anagrams_found: u64, last_anagram: Option<&str>) {
+        self.last_report = Instant::now();
+        let event = self.event("progress", anagrams_found);
+
+        match self.format {
+            ProgressFormat::Text => {
+                if console::get_verbosity() >= 1 {
+                    let eta = match event.eta_seconds {
+                        Some(eta) => format!("{:.0}s", eta),
+                        None => "?".to_string(),
+                    };
+                    console::print_progress(&format!(
+                        "[{}/{} sets, {:.1}%, ETA {}] {}: {}{}",
+                        event.sets_explored,
+                        event.sets_total,
+                        event.completed_fraction * 100.0,
+                        eta,
+                        event.anagrams_found,
+                        last_anagram.unwrap_or(""),
+                        " ".repeat(30)
+                    ));
+                }
+            }
+            ProgressFormat::Json => Self::emit(&event),
+        }
+    }
+
+    /// Reports the end of the search (JSON format only: the text line is simply left as it is).
+    pub fn finish(&mut self, anagrams_found: u64) {
+        if self.format == ProgressFormat::Json {
+            Self::emit(&self.event("done", anagrams_found));
+        }
+    }
+
+    fn emit(event: &ProgressEvent) {
+        if let Ok(json) = serde_json::to_string(event) {
+            let _ = writeln!(io::stderr(), "{}", json);
+        }
+    }
+
+    /// Builds an event from the current counters. The ETA assumes that the remaining cost is searched at the same
+    /// speed as the completed one.
+    fn event(&self, event: &'static str, anagrams_found: u64) -> ProgressEvent {
+        let sets_explored = self.explored_sets.load(Ordering::Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let completed_cost = f64::from_bits(self.completed_cost.load(Ordering::Relaxed));
+        let completed_fraction = if self.total_cost > 0.0 {
+            (completed_cost / self.total_cost).min(1.0)
+        } else {
+            1.0
+        };
+
+        let anagrams_per_second = if elapsed > 0.0 {
+            anagrams_found as f64 / elapsed
+        } else {
+            0.0
+        };
+        let eta_seconds = (completed_fraction > 0.0)
+            .then(|| elapsed * (1.0 - completed_fraction) / completed_fraction);
+
+        ProgressEvent {
+            event,
+            sets_explored,
+            sets_total: self.sets_number,
+            anagrams_found,
+            completed_fraction,
+            elapsed_seconds: elapsed,
+            anagrams_per_second,
+            eta_seconds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_reporter(format: ProgressFormat, completed_cost: Arc<AtomicU64>) -> ProgressReporter {
+        ProgressReporter::new(
+            format,
+            Arc::new(AtomicU64::new(0)),
+            4,
+            completed_cost,
+            100.0,
+        )
+    }
+
+    #[test]
+    fn test_event_eta() {
+        let completed_cost = Arc::new(AtomicU64::new(0));
+        let reporter = new_reporter(ProgressFormat::Json, completed_cost.clone());
+
+        let event = reporter.event("progress", 0);
+        assert_eq!(event.sets_total, 4);
+        assert_eq!(event.eta_seconds, None);
+
+        // Half the cost completed: the remaining half should take about as long as the elapsed time
+        std::thread::sleep(Duration::from_millis(10));
+        add_completed_cost(&completed_cost, 20.0);
+        add_completed_cost(&completed_cost, 30.0);
+        let event = reporter.event("progress", 10);
+        assert_eq!(event.completed_fraction, 0.5);
+        let eta = event.eta_seconds.unwrap();
+        assert!((eta - event.elapsed_seconds).abs() < 1e-9);
+        assert!(event.anagrams_per_second > 0.0);
+    }
+
+    #[test]
+    fn test_report_interval() {
+        let mut reporter = new_reporter(ProgressFormat::Text, Arc::new(AtomicU64::new(0)));
+        assert!(!reporter.is_due());
+        assert!(reporter.time_to_next_report() <= REPORT_INTERVAL);
+
+        reporter.last_report -= REPORT_INTERVAL;
+        assert!(reporter.is_due());
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeSet;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::search::{bounded_multisets_number, multisets_number};
+
+/// Draws `n` distinct indices uniformly from `0..total` (all of them if n >= total), sorted.
+/// Uses Floyd's algorithm with a portable generator, so the same seed always gives the same indices.
+pub fn draw_indices(total: u64, n: u64, seed: u64) -> Vec<u64> {
+    if n >= total {
+        return (0..total).collect();
+    }
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut chosen = BTreeSet::new();
+    for j in total - n..total {
+        let t = rng.random_range(0..=j);
+        if !chosen.insert(t) {
+            chosen.insert(j);
+        }
+    }
+
+    chosen.into_iter().collect()
+}
+
+/// Returns the index-th k-multiset of 0..m, as a non-decreasing sequence, in lexicographic order.
+pub fn nth_multiset(m: u64, k: u64, mut index: u64) -> Vec<u64> {
+    debug_assert!(index < multisets_number(m, k));
+
+    let mut multiset = Vec::with_capacity(k as usize);
+    let mut low = 0;
+    for remaining in (0..k).rev() {
+        // Multisets starting with v: the remaining elements are drawn from v..m
+        let mut v = low;
+        loop {
+            let with_v = multisets_number(m - v, remaining);
+            if index < with_v {
+                break;
+            }
+            index -= with_v;
+            v += 1;
+        }
+        multiset.push(v);
+        low = v;
+    }
+
+    multiset
+}
+
+/// Returns the index-th k-multiset of 0..m in which no element occurs more than max_repeat times, as a
+/// non-decreasing sequence, in lexicographic order.
+pub fn nth_bounded_multiset(m: u64, k: u64, max_repeat: u64, mut index: u64) -> Vec<u64> {
+    debug_assert!(index < bounded_multisets_number(m, k, max_repeat));
+
+    let mut multiset = Vec::with_capacity(k as usize);
+    let mut remaining = k;
+    for v in 0..m {
+        // More copies of v come first: the sequence stays smaller for longer
+        for copies in (0..=remaining.min(max_repeat)).rev() {
+            let with_copies = bounded_multisets_number(m - v - 1, remaining - copies, max_repeat);
+            if index < with_copies {
+                multiset.extend(std::iter::repeat_n(v, copies as usize));
+                remaining -= copies;
+                break;
+            }
+            index -= with_copies;
+        }
+    }
+
+    multiset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_indices_is_reproducible() {
+        let a = draw_indices(1_000_000, 10, 42);
+        let b = draw_indices(1_000_000, 10, 42);
+        let c = draw_indices(1_000_000, 10, 43);
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.len(), 10);
+        assert!(a.windows(2).all(|w| w[0] < w[1]));
+        assert!(a.iter().all(|&i| i < 1_000_000));
+    }
+
+    #[test]
+    fn test_draw_indices_small_total() {
+        assert_eq!(draw_indices(3, 10, 1), vec![0, 1, 2]);
+        assert!(draw_indices(0, 10, 1).is_empty());
+    }
+
+    #[test]
+    fn test_nth_multiset_enumerates_in_order() {
+        // 2-multisets of {0, 1, 2}: 00 01 02 11 12 22
+        let all: Vec<Vec<u64>> = (0..multisets_number(3, 2))
+            .map(|i| nth_multiset(3, 2, i))
+            .collect();
+        assert_eq!(
+            all,
+            vec![
+                vec![0, 0],
+                vec![0, 1],
+                vec![0, 2],
+                vec![1, 1],
+                vec![1, 2],
+                vec![2, 2]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nth_bounded_multiset() {
+        // 3-multisets of {0, 1, 2}, every element at most twice
+        let all: Vec<Vec<u64>> = (0..bounded_multisets_number(3, 3, 2))
+            .map(|i| nth_bounded_multiset(3, 3, 2, i))
+            .collect();
+        assert_eq!(all.len(), 7);
+        assert_eq!(all[0], vec![0, 0, 1]);
+        assert_eq!(all[6], vec![1, 2, 2]);
+        assert!(all.windows(2).all(|w| w[0] < w[1]));
+
+        // Without a binding bound, the order is the one of nth_multiset()
+        for i in 0..multisets_number(4, 2) {
+            assert_eq!(nth_bounded_multiset(4, 2, 2, i), nth_multiset(4, 2, i));
+        }
+    }
+}
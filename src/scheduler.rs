@@ -0,0 +1,295 @@
+use std::ops::Range;
+
+use crate::combinations::RepeatedCombinationsWithSum;
+use crate::dictionarium::{Dictionarium, MAX_WORD_LENGTH, Section};
+use crate::signature::Signature;
+
+/// How many tasks each worker should get on average. More tasks mean finer load balancing at the end of the run.
+const TASKS_PER_WORKER: f64 = 8.0;
+
+/// Signatures sampled per section when estimating how often two words fit in the target together.
+const FIT_SAMPLE_SIZE: usize = 64;
+
+/// A unit of work for a search thread: a length combination, restricted to a range of the signatures in its first slot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Task {
+    pub set_index: usize,
+    pub first_slot: Range<usize>,
+    pub cost: f64,
+}
+
+/// Estimates the cost of searching length combinations, in signatures tried.
+/// The search tries every signature of a slot for each partial solution that survived the previous slots, so the
+/// cost is a sum over the slots of survivors × section size. The product of the section sizes alone overestimates
+/// deep combinations by orders of magnitude: most pairs of words don't fit in the target together. The survival
+/// rate of each slot is estimated from a sample of word pairs of the lengths of the slot and the previous one.
+pub struct CostModel {
+    sizes: Vec<f64>,    // index = word length, signatures in the section
+    fit: Vec<Vec<f64>>, // fit[a][b] = fraction of (length a, length b) pairs of signatures that fit in the target
+}
+
+impl CostModel {
+    /// Builds the model for a dictionary filtered on the target. In a letter bank words may reuse the letters, so every
+    /// pair fits: the target only holds the required letters, sampling it would rate almost every pair as a misfit.
+    pub fn new(
+        dictionarium: &Dictionarium,
+        target_signature: &Signature,
+        letter_bank: bool,
+    ) -> Self {
+        let samples: Vec<Vec<&Signature>> = (0..=MAX_WORD_LENGTH)
+            .map(|len| {
+                let section = dictionarium.get_section(len);
+                let step = section.len().div_ceil(FIT_SAMPLE_SIZE).max(1);
+                section.keys().step_by(step).collect()
+            })
+            .collect();
+
+        let mut fit = vec![vec![0.0; MAX_WORD_LENGTH + 1]; MAX_WORD_LENGTH + 1];
+        for a in 0..=MAX_WORD_LENGTH {
+            for b in a..=MAX_WORD_LENGTH {
+                if samples[a].is_empty() || samples[b].is_empty() {
+                    continue;
+                }
+                if letter_bank {
+                    fit[a][b] = 1.0;
+                    fit[b][a] = 1.0;
+                    continue;
+                }
+
+                let mut fitting = 0;
+                for x in &samples[a] {
+                    for y in &samples[b] {
+                        let mut pair = **x;
+                        pair.add(y);
+                        if pair.is_subset_of(target_signature) {
+                            fitting += 1;
+                        }
+                    }
+                }
+                fit[a][b] = fitting as f64 / (samples[a].len() * samples[b].len()) as f64;
+                fit[b][a] = fit[a][b];
+            }
+        }
+
+        Self {
+            sizes: (0..=MAX_WORD_LENGTH)
+                .map(|len| dictionarium.get_section(len).len() as f64)
+                .collect(),
+            fit,
+        }
+    }
+
+    /// Estimates the cost of searching a length combination.
+    pub fn estimate(&self, set: &[usize]) -> f64 {
+        let mut cost = 0.0;
+        let mut survivors = 1.0;
+        for (i, &len) in set.iter().enumerate() {
+            cost += survivors * self.sizes[len];
+            survivors *= self.sizes[len];
+            if i > 0 {
+                survivors *= self.fit[set[i - 1]][len];
+            }
+        }
+        cost
+    }
+}
+
+/// Returns true if the words of a length combination can cover every letter of the target.
+pub fn is_feasible(
+    dictionarium: &Dictionarium,
+    target_signature: &Signature,
+    set: &[usize],
+) -> bool {
+    target_signature.is_subset_of(&dictionarium.get_coverage(set))
+}
+
+/// Builds the task list: length combinations heavier than the average task are split by ranges of their first slot,
+/// then everything is sorted largest-first so that the long tasks don't end up running alone on one core.
+/// Infeasible length combinations get no task at all, unless the search is partial: the letters of the target then
+/// don't all have to be covered. With a pattern, the first slot ranges index its first slot section, like the search.
+/// In a letter bank the target holds the required letters.
+pub fn plan_tasks(
+    dictionarium: &Dictionarium,
+    target_signature: &Signature,
+    rcs: &RepeatedCombinationsWithSum,
+    slot_sections: Option<&[Section]>,
+    workers_number: usize,
+    partial: bool,
+    letter_bank: bool,
+) -> Vec<Task> {
+    let cost_model = CostModel::new(dictionarium, target_signature, letter_bank);
+    let costs: Vec<f64> = (0..rcs.get_sets_number())
+        .map(|i| {
+            let set = rcs.get_set(i);
+            if partial || is_feasible(dictionarium, target_signature, set) {
+                cost_model.estimate(set)
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    let total_cost: f64 = costs.iter().sum();
+    let chunk_cost = total_cost / (workers_number as f64 * TASKS_PER_WORKER);
+
+    let mut tasks = Vec::with_capacity(costs.len());
+    for (set_index, &cost) in costs.iter().enumerate() {
+        if cost == 0.0 {
+            continue;
+        }
+
+        let first_section_size = match slot_sections {
+            Some(slot_sections) => slot_sections[0].len(),
+            None => dictionarium.get_section(rcs.get_set(set_index)[0]).len(),
+        };
+
+        let chunks_number = if chunk_cost > 0.0 && cost > chunk_cost {
+            ((cost / chunk_cost).ceil() as usize).clamp(1, first_section_size.max(1))
+        } else {
+            1
+        };
+
+        for c in 0..chunks_number {
+            let start = first_section_size * c / chunks_number;
+            let end = first_section_size * (c + 1) / chunks_number;
+            tasks.push(Task {
+                set_index,
+                first_slot: start..end,
+                cost: cost / chunks_number as f64,
+            });
+        }
+    }
+
+    tasks.sort_by(|a, b| b.cost.total_cmp(&a.cost)); // Stable: ties keep the combination order
+    tasks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::WordPattern;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn create_dictionarium(words: &[&str], target_text: &str) -> Dictionarium {
+        let mut tmp_file = NamedTempFile::new().unwrap();
+        for word in words {
+            writeln!(tmp_file, "{}", word).unwrap();
+        }
+
+        let mut dict = Dictionarium::new();
+        dict.read_word_list(tmp_file.path().to_str().unwrap(), target_text)
+            .unwrap();
+        dict
+    }
+
+    #[test]
+    fn test_heavy_sets_are_split_and_sorted() {
+        // 4 signatures of length 2, 1 of length 4: [2, 2] costs 4 + 4 * 4, [4] costs 1
+        let dict = create_dictionarium(&["ab", "cd", "ac", "bd", "abcd"], "abcd");
+        let rcs = RepeatedCombinationsWithSum::new(4, 1, 2, vec![2, 4]);
+        let tasks = plan_tasks(&dict, &Signature::new("abcd"), &rcs, None, 1, false, false);
+
+        // Costs are decreasing
+        for w in tasks.windows(2) {
+            assert!(w[0].cost >= w[1].cost);
+        }
+
+        // The light set [4] is left whole
+        let light_index = (0..2).find(|&i| rcs.get_set(i) == &vec![4]).unwrap();
+        let light_tasks: Vec<&Task> = tasks
+            .iter()
+            .filter(|t| t.set_index == light_index)
+            .collect();
+        assert_eq!(light_tasks.len(), 1);
+        assert_eq!(light_tasks[0].first_slot, 0..1);
+
+        // The heavy set's first slot is covered exactly once by contiguous ranges
+        let heavy_index = 1 - light_index;
+        let mut ranges: Vec<Range<usize>> = tasks
+            .iter()
+            .filter(|t| t.set_index == heavy_index)
+            .map(|t| t.first_slot.clone())
+            .collect();
+        ranges.sort_by_key(|r| r.start);
+        assert!(ranges.len() > 1);
+        assert_eq!(ranges[0].start, 0);
+        assert_eq!(ranges.last().unwrap().end, 4);
+        for w in ranges.windows(2) {
+            assert_eq!(w[0].end, w[1].start);
+        }
+    }
+
+    #[test]
+    fn test_split_is_bounded_by_first_section() {
+        // A single set is always heavier than the average task, but its first slot has only 2 signatures
+        let dict = create_dictionarium(&["ab", "cd"], "abcd");
+        let rcs = RepeatedCombinationsWithSum::new(4, 2, 2, vec![2]);
+        let tasks = plan_tasks(&dict, &Signature::new("abcd"), &rcs, None, 4, false, false);
+
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].first_slot.len() + tasks[1].first_slot.len(), 2);
+    }
+
+    #[test]
+    fn test_split_follows_slot_sections() {
+        // The pattern "a? ??" keeps only "ab" and "ac" in the first slot, out of the 4 signatures of length 2
+        let dict = create_dictionarium(&["ab", "cd", "ac", "bd"], "abcd");
+        let slot_sections = WordPattern::parse("a? ??").unwrap().slot_sections(&dict);
+        let rcs = RepeatedCombinationsWithSum::new(4, 2, 2, vec![2]);
+        let tasks = plan_tasks(
+            &dict,
+            &Signature::new("abcd"),
+            &rcs,
+            Some(&slot_sections),
+            4,
+            false,
+            false,
+        );
+
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks.iter().map(|t| t.first_slot.len()).sum::<usize>(), 2);
+        assert!(tasks.iter().all(|t| t.first_slot.end <= 2));
+    }
+
+    #[test]
+    fn test_cost_model_pruning_correction() {
+        // Among the pairs of 2-letter signatures, only ab+cd, cd+ab, ac+bd, bd+ac fit in "abcd"
+        let dict = create_dictionarium(&["ab", "cd", "ac", "bd", "abcd"], "abcd");
+        let model = CostModel::new(&dict, &Signature::new("abcd"), false);
+
+        assert_eq!(model.fit[2][2], 0.25);
+        assert_eq!(model.estimate(&[4]), 1.0);
+        assert_eq!(model.estimate(&[2, 2]), 4.0 + 4.0 * 4.0);
+        // Third slot: the 4 surviving pairs try the 4 signatures again
+        assert_eq!(model.estimate(&[2, 2, 2]), 4.0 + 16.0 + 4.0 * 4.0);
+    }
+
+    #[test]
+    fn test_cost_model_letter_bank() {
+        // Bank "abc", "a" required: the words may reuse the letters, every pair fits
+        let mut tmp_file = NamedTempFile::new().unwrap();
+        for word in ["ab", "bc", "ca", "abc"] {
+            writeln!(tmp_file, "{}", word).unwrap();
+        }
+        let mut dict = Dictionarium::new();
+        dict.set_letter_bank(true);
+        dict.read_word_list(tmp_file.path().to_str().unwrap(), "abc")
+            .unwrap();
+
+        let model = CostModel::new(&dict, &Signature::new("a"), true);
+        assert_eq!(model.fit[2][2], 1.0);
+        assert_eq!(model.fit[2][3], 1.0);
+        assert_eq!(model.estimate(&[2, 2]), 3.0 + 3.0 * 3.0);
+    }
+
+    #[test]
+    fn test_infeasible_sets_get_no_task() {
+        // The only 2-letter words lack the "d": [2, 2] is infeasible, [4] is not
+        let dict = create_dictionarium(&["ab", "bc", "abcd"], "abcd");
+        let rcs = RepeatedCombinationsWithSum::new(4, 1, 2, vec![2, 4]);
+        let tasks = plan_tasks(&dict, &Signature::new("abcd"), &rcs, None, 2, false, false);
+
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(rcs.get_set(tasks[0].set_index), &vec![4]);
+    }
+}
@@ -1,12 +1,118 @@
+use std::ops::Range;
 use std::sync::Arc;
 
 use ahash::HashSetExt;
 use crossbeam_channel::Sender;
 use fxhash::FxHashSet;
+use serde::{Deserialize, Serialize};
 
-use crate::dictionarium::Dictionarium;
+use crate::compact::{format_group, format_included};
+use crate::dictionarium::{Dictionarium, Section};
+use crate::memo::{MAX_COMPLETIONS, MemoCache, MemoKey};
+use crate::sampling::{nth_bounded_multiset, nth_multiset};
 use crate::signature::Signature;
 
+/// What the search does with the signature solutions it finds.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputMode {
+    Anagrams,  // Expands them into text anagrams and sends them to the IO thread
+    CountOnly, // Only counts the anagrams they would expand into
+    Compact, // Sends one line per signature solution, grouping the alternative words: {bar|bra} {man|nam}
+}
+
+/// An output line, tagged with the index of the length combination it comes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Anagram {
+    pub set_index: usize,
+    pub text: String,
+}
+
+/// Counters of a search, summed over the tasks for the run summary.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct SearchStats {
+    pub nodes_visited: u64, // Calls to search(), i.e. partial solutions that survived the pruning
+    pub pruned_by_subset: u64, // Signatures rejected because they don't fit in the letters left
+    pub pruned_by_coverage: u64, // Signatures rejected because the next slots can't cover the letters left
+    pub memo_replays: u64, // Sub-problems answered by the memo cache instead of being searched
+    pub signature_solutions: u64, // Canonical solutions, each one standing for one or more anagrams
+    pub anagrams_emitted: u64, // Lines sent to the IO thread
+    pub duplicates_suppressed: u64, // Anagrams built twice from the same solution and sent only once
+    pub pruned_by_limits: u64, // Signatures rejected because they would repeat a word too often
+    pub pruned_by_order: u64, // Signatures rejected because they come before the previous interchangeable word
+}
+
+impl SearchStats {
+    /// Adds the counters of another search.
+    pub fn add(&mut self, other: &SearchStats) {
+        self.nodes_visited += other.nodes_visited;
+        self.pruned_by_subset += other.pruned_by_subset;
+        self.pruned_by_coverage += other.pruned_by_coverage;
+        self.memo_replays += other.memo_replays;
+        self.signature_solutions += other.signature_solutions;
+        self.anagrams_emitted += other.anagrams_emitted;
+        self.duplicates_suppressed += other.duplicates_suppressed;
+        self.pruned_by_limits += other.pruned_by_limits;
+        self.pruned_by_order += other.pruned_by_order;
+    }
+}
+
+/// How far a near-anagram may be from the target: words may add up to add letters that the target lacks, and leave
+/// up to drop letters of the target unused.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tolerance {
+    pub add: usize,
+    pub drop: usize,
+}
+
+impl Tolerance {
+    /// Parses a tolerance such as "add=1,drop=1". A missing key counts as 0.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut tolerance = Self::default();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid tolerance {}, expected e.g. add=1,drop=1", s))?;
+            let value: usize = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid letter count in tolerance {}", s))?;
+            match key.trim() {
+                "add" => tolerance.add = value,
+                "drop" => tolerance.drop = value,
+                key => return Err(format!("Unknown tolerance {}, expected add or drop", key)),
+            }
+        }
+        Ok(tolerance)
+    }
+
+    /// Returns true if only exact anagrams are accepted.
+    pub fn is_exact(&self) -> bool {
+        self.add == 0 && self.drop == 0
+    }
+}
+
+impl std::fmt::Display for Tolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "add={},drop={}", self.add, self.drop)
+    }
+}
+
+/// Limits on the words of an anagram, applied by the search rather than to its output. They only count the words
+/// searched, not the included text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WordLimits {
+    pub max_repeat: Option<u64>, // Times a word may occur, 1 for distinct words
+    pub max_short: Option<(u64, usize)>, // (n, l): at most n words of length l or less
+}
+
+/// Picks given anagrams out of the sequence of all the anagrams of a length combination, in enumeration order.
+struct Sampler {
+    targets: Vec<u64>, // Sorted indices of the anagrams to pick
+    next: usize,       // First target not picked yet
+    offset: u64,       // Index of the first anagram of the current solution
+    samples: Vec<(u64, String)>,
+}
+
 /// Temporary mutable state passed during the recursive search.
 /// This needs to be decoupled from SearchThread, otherwise recursion is not possible because the borrow checker
 /// prevents &self and &mut self from existing at the same time.
@@ -14,6 +120,11 @@ struct SearchState {
     ws: Signature,
     solution: Vec<Signature>,
     anagram_set: FxHashSet<String>,
+    recorders: Vec<Option<Vec<Signature>>>, // Completions found below each memoizable index, None if not recording
+    anagrams_number: u64,
+    sampler: Option<Sampler>,
+    stopped: bool, // Set when there is nothing left to search for
+    stats: SearchStats,
 }
 
 /// Not a real thread, more like a searcher object with a state and options.
@@ -24,7 +135,19 @@ pub struct SearchThread {
     included_text: Arc<String>,
     word_lengths: Vec<usize>,
     words_number: usize,
-    sender: Sender<String>,
+    sender: Sender<Anagram>,
+    memo: Arc<MemoCache>,
+    set_index: usize, // Index of word_lengths among the length combinations, attached to the output lines
+    first_slot: Range<usize>, // Range of the first section's signatures to try, in iteration order
+    coverage: Vec<Signature>, // coverage[i] = letters that the words from the i-th slot onward can cover at most
+    output_mode: OutputMode,
+    slot_sections: Option<Arc<Vec<Section>>>, // Sections of a pattern, one per slot, see set_slot_sections()
+    partial: bool,                            // Solutions may leave letters of the target unused
+    blanks: usize, // Blank tiles, each one standing for a letter missing from the target
+    tolerance: Tolerance, // Letters the solutions may add to the target or drop from it
+    letter_bank: bool, // Words reuse the letters of a bank, the target only holds the required ones
+    limits: WordLimits,
+    stats: SearchStats, // Counters of the last run
 }
 
 impl SearchThread {
@@ -33,9 +156,13 @@ impl SearchThread {
         target_signature: Arc<Signature>,
         included_text: Arc<String>,
         word_lengths: Vec<usize>,
-        sender: Sender<String>,
+        sender: Sender<Anagram>,
+        memo: Arc<MemoCache>,
     ) -> Self {
         let words_number = word_lengths.len();
+        let coverage = (0..=words_number)
+            .map(|i| dictionarium.get_coverage(&word_lengths[i..]))
+            .collect();
 
         Self {
             dictionarium,
@@ -44,41 +171,310 @@ impl SearchThread {
             word_lengths,
             words_number,
             sender,
+            memo,
+            set_index: 0,
+            first_slot: 0..usize::MAX,
+            coverage,
+            output_mode: OutputMode::Anagrams,
+            slot_sections: None,
+            partial: false,
+            blanks: 0,
+            tolerance: Tolerance::default(),
+            letter_bank: false,
+            limits: WordLimits::default(),
+            stats: SearchStats::default(),
+        }
+    }
+
+    /// Searches the words of every slot in its own section (see pattern.rs) instead of the dictionary section of its
+    /// length. Slots are then not interchangeable: every order is a solution of its own, and words keep the slot
+    /// order in the output. The memo stays valid as long as all the tasks share the same slots, since the remaining
+    /// lengths tell the slot.
+    pub fn set_slot_sections(&mut self, slot_sections: Arc<Vec<Section>>) {
+        debug_assert_eq!(slot_sections.len(), self.words_number);
+
+        for i in (0..self.words_number).rev() {
+            let mut slot_coverage = Signature::new_empty();
+            for ws in slot_sections[i].keys() {
+                slot_coverage.union(ws);
+            }
+            self.coverage[i] = self.coverage[i + 1];
+            self.coverage[i].add_saturating(&slot_coverage);
         }
+        self.slot_sections = Some(slot_sections);
+    }
+
+    /// Sets the index of the length combination, which tags the output lines.
+    pub fn set_set_index(&mut self, set_index: usize) {
+        self.set_index = set_index;
+    }
+
+    /// Restricts the search to a range of the signatures of the first slot, so that heavy length combinations
+    /// can be split among several tasks. Sections are never modified, so their iteration order is stable.
+    pub fn set_first_slot_range(&mut self, range: Range<usize>) {
+        self.first_slot = range;
     }
 
-    /// Launches the search.
-    pub fn run(&mut self) {
-        let mut state = SearchState {
+    /// Sets what to do with the solutions found.
+    pub fn set_output_mode(&mut self, mode: OutputMode) {
+        self.output_mode = mode;
+    }
+
+    /// Accepts the solutions that leave letters of the target unused (the length combination tells how many).
+    /// The coverage pruning is then disabled, since the letters left don't have to be covered.
+    pub fn set_partial(&mut self, partial: bool) {
+        self.partial = partial;
+    }
+
+    /// Lets the words use up to blanks letters that the target lacks; the word lengths must then add up to the letters
+    /// of the target plus the blanks. Every letter of the target must still be used, so the coverage pruning holds.
+    pub fn set_blanks(&mut self, blanks: usize) {
+        self.blanks = blanks;
+    }
+
+    /// Accepts near-anagrams: the words may add up to tolerance.add letters that the target lacks, like optional
+    /// blanks, and leave up to tolerance.drop letters of the target unused, which the coverage pruning then allows for.
+    pub fn set_tolerance(&mut self, tolerance: Tolerance) {
+        self.tolerance = tolerance;
+    }
+
+    /// Searches phrases spelled with the letters of a bank, reusing them freely: the dictionary must hold only such
+    /// words, and the target signature then holds the letters required in every phrase, once each. Any phrase of
+    /// the word lengths that contains them is a solution.
+    pub fn set_letter_bank(&mut self, letter_bank: bool) {
+        self.letter_bank = letter_bank;
+    }
+
+    /// Limits the repetitions of a word and the number of short words. Short words only depend on the length
+    /// combination, which is skipped as a whole; a signature is pruned as soon as it occurs more times than its
+    /// words can fill without repeating one too often, and the expansion skips the anagrams that still do.
+    /// The repetition limit needs interchangeable slots, so no slot sections.
+    pub fn set_word_limits(&mut self, limits: WordLimits) {
+        debug_assert!(limits.max_repeat.is_none() || !self.is_ordered());
+        self.limits = limits;
+    }
+
+    /// Returns true if the word lengths respect the limit on short words.
+    fn has_allowed_lengths(&self) -> bool {
+        self.limits.max_short.is_none_or(|(n, max_length)| {
+            self.word_lengths
+                .iter()
+                .filter(|&&l| l <= max_length)
+                .count() as u64
+                <= n
+        })
+    }
+
+    /// Returns true if the signature can take one more slot: with max_repeat, the k slots of a signature with m
+    /// words need k <= max_repeat * m.
+    fn may_repeat(&self, word_index: usize, sig: &Signature, solution: &[Signature]) -> bool {
+        self.limits.max_repeat.is_none_or(|n| {
+            let slots = solution[..word_index].iter().filter(|&s| s == sig).count() as u64 + 1;
+            slots <= n.saturating_mul(self.get_words(word_index, sig).len() as u64)
+        })
+    }
+
+    /// Number of anagrams a run of k equal signatures with m words expands into.
+    fn run_weight(&self, m: u64, k: u64) -> u64 {
+        match self.limits.max_repeat {
+            Some(n) => bounded_multisets_number(m, k, n),
+            None => multisets_number(m, k),
+        }
+    }
+
+    /// Returns the number of letters the words may have beyond the ones of the target.
+    #[inline(always)]
+    fn extra_letters(&self) -> usize {
+        self.blanks + self.tolerance.add
+    }
+
+    /// Returns true if the remaining letters and lengths of a sub-problem tell its completions. Letters beyond the
+    /// target are fine only if the word lengths add up to a known total, i.e. if the search is exact apart from the
+    /// blanks.
+    fn is_memoizable(&self) -> bool {
+        // Repetitions depend on the slots before the sub-problem
+        self.limits.max_repeat.is_none()
+            && (self.extra_letters() == 0 || (!self.partial && self.tolerance.is_exact()))
+    }
+
+    /// Returns the letters of the target that a partial solution leaves to the next slots.
+    #[inline(always)]
+    fn remaining(&self, ws: &Signature) -> Signature {
+        if self.extra_letters() == 0 {
+            let mut remaining = *self.target_signature;
+            remaining.sub(ws);
+            remaining
+        } else {
+            self.target_signature.difference(ws)
+        }
+    }
+
+    /// Returns true if the words of these lengths may cover the target, as far as the coverage can tell.
+    fn may_cover_target(&self) -> bool {
+        self.partial
+            || self
+                .target_signature
+                .is_subset_with_blanks(&self.coverage[0], self.tolerance.drop)
+    }
+
+    /// Launches the search. Returns the number of anagrams found (sent or counted, depending on the output mode;
+    /// in compact mode, the number of anagrams the sent lines expand into).
+    pub fn run(&mut self) -> u64 {
+        // A letter that no word of these lengths contains makes the whole length combination impossible
+        if !self.may_cover_target() || !self.has_allowed_lengths() {
+            return 0;
+        }
+
+        let mut state = self.new_state(None);
+        self.search(0, &mut state);
+        self.stats = state.stats;
+        state.anagrams_number
+    }
+
+    /// Returns the counters of the last run.
+    pub fn get_stats(&self) -> SearchStats {
+        self.stats
+    }
+
+    /// Launches the search, only building the anagrams with the given indices in the enumeration order of this
+    /// length combination. The order only depends on the dictionary, so the same indices always give the same anagrams.
+    /// Returns (index, anagram) pairs. The first slot range is ignored: the whole combination must be enumerated.
+    pub fn run_sample(&mut self, targets: Vec<u64>) -> Vec<(u64, String)> {
+        debug_assert!(targets.windows(2).all(|w| w[0] < w[1]));
+        if targets.is_empty() || !self.may_cover_target() || !self.has_allowed_lengths() {
+            return Vec::new();
+        }
+
+        self.first_slot = 0..usize::MAX;
+        let mut state = self.new_state(Some(Sampler {
+            targets,
+            next: 0,
+            offset: 0,
+            samples: Vec::new(),
+        }));
+        self.search(0, &mut state);
+
+        state.sampler.map(|s| s.samples).unwrap_or_default()
+    }
+
+    fn new_state(&self, sampler: Option<Sampler>) -> SearchState {
+        SearchState {
             ws: Signature::new_empty(),
             solution: vec![Signature::new_empty(); self.words_number],
             anagram_set: FxHashSet::new(),
-        };
-
-        self.search(0, &mut state);
+            recorders: vec![None; self.words_number],
+            anagrams_number: 0,
+            sampler,
+            stopped: false,
+            stats: SearchStats::default(),
+        }
     }
 
     /// Recursive search function.
     fn search(&self, word_index: usize, state: &mut SearchState) {
+        state.stats.nodes_visited += 1;
+
         // Base case
         debug_assert!(word_index <= self.words_number);
         if word_index == self.words_number {
-            if state.ws == *self.target_signature {
-                //If an anagram is found
-                self.compute_solution(state);
+            if self.partial
+                || state.ws == *self.target_signature
+                || self.remaining(&state.ws).get_char_number() <= self.tolerance.drop
+            {
+                //If an anagram is found (the pruning keeps ws a subset of the target, extra letters aside)
+                self.found_solution(state);
             }
             return;
         }
 
-        let len = self.word_lengths[word_index];
-        let section = self.dictionarium.get_section(len);
+        // Sub-problems below the first word are memoized on (remaining letters, remaining lengths)
+        if word_index >= 1 && self.memo.is_enabled() && self.is_memoizable() {
+            let remaining = self.remaining(&state.ws);
+
+            let lower_bound = self.lower_bound(word_index, &state.solution);
+            if let Some(key) =
+                MemoKey::new(&remaining, &self.word_lengths[word_index..], lower_bound)
+            {
+                if let Some(completions) = self.memo.get(&key) {
+                    state.stats.memo_replays += 1;
+                    // Replays the cached completions as if they had just been found
+                    for completion in completions.chunks_exact(self.words_number - word_index) {
+                        state.solution[word_index..].copy_from_slice(completion);
+                        self.found_solution(state);
+                        if state.stopped {
+                            break;
+                        }
+                    }
+                    return;
+                }
+
+                state.recorders[word_index] = Some(Vec::new());
+                self.explore(word_index, state);
+                // A stopped search only saw part of the completions
+                if let Some(completions) = state.recorders[word_index].take()
+                    && !state.stopped
+                {
+                    self.memo.insert(key, completions);
+                }
+                return;
+            }
+        }
+
+        self.explore(word_index, state);
+    }
+
+    /// Tries every signature of the current section in the word_index-th slot.
+    fn explore(&self, word_index: usize, state: &mut SearchState) {
+        let section = self.get_section(word_index);
+        let (skip, take) = if word_index == 0 {
+            (self.first_slot.start, self.first_slot.len())
+        } else {
+            (0, usize::MAX)
+        };
+
+        // In a letter bank, a word only counts for the required letters still missing, which keeps ws within them
+        let missing = self.letter_bank.then(|| self.remaining(&state.ws));
+        let lower_bound = self.lower_bound(word_index, &state.solution);
 
-        for (current_signature, _) in section {
-            state.ws.add(current_signature);
+        for current_signature in section.keys().skip(skip).take(take) {
+            // Order block: only the canonical permutation of interchangeable words is explored
+            if lower_bound.is_some_and(|bound| *current_signature < bound) {
+                state.stats.pruned_by_order += 1;
+                continue;
+            }
+
+            let letters = match &missing {
+                Some(missing) => current_signature.intersection(missing),
+                None => *current_signature,
+            };
+            state.ws.add(&letters);
 
             // Pruning block
-            if word_index >= 1 && !state.ws.is_subset_of(&self.target_signature) {
-                state.ws.sub(current_signature);
+            if !state
+                .ws
+                .is_subset_with_blanks(&self.target_signature, self.extra_letters())
+            {
+                state.ws.sub(&letters);
+                state.stats.pruned_by_subset += 1;
+                continue;
+            }
+
+            // Limits block
+            if !self.may_repeat(word_index, current_signature, &state.solution) {
+                state.ws.sub(&letters);
+                state.stats.pruned_by_limits += 1;
+                continue;
+            }
+
+            // Feasibility block: the letters left must still be coverable by the words of the remaining slots, apart
+            // from the ones a near-anagram may drop
+            let remaining = self.remaining(&state.ws);
+            if !self.partial
+                && !remaining
+                    .is_subset_with_blanks(&self.coverage[word_index + 1], self.tolerance.drop)
+            {
+                state.ws.sub(&letters);
+                state.stats.pruned_by_coverage += 1;
                 continue;
             }
 
@@ -88,8 +484,184 @@ impl SearchThread {
             self.search(word_index + 1, state);
 
             // Backtracking
-            state.ws.sub(current_signature);
+            state.ws.sub(&letters);
+
+            if state.stopped {
+                return;
+            }
+        }
+    }
+
+    /// Called on every signature solution: records it in the memo recorders of the enclosing sub-problems, then outputs it.
+    fn found_solution(&self, state: &mut SearchState) {
+        for i in 1..self.words_number {
+            if let Some(recorder) = &mut state.recorders[i] {
+                if recorder.len() >= MAX_COMPLETIONS * (self.words_number - i) {
+                    state.recorders[i] = None; // Too many completions, this sub-problem won't be cached
+                } else {
+                    recorder.extend_from_slice(&state.solution[i..]);
+                }
+            }
+        }
+
+        state.stats.signature_solutions += 1;
+
+        if state.sampler.is_some() {
+            self.sample_solution(state);
+            return;
+        }
+
+        match self.output_mode {
+            OutputMode::Anagrams => self.compute_solution(state),
+            OutputMode::CountOnly => {
+                let weight = self.solution_weight(&state.solution);
+                state.anagrams_number = state.anagrams_number.saturating_add(weight);
+            }
+            OutputMode::Compact => {
+                self.compute_compact_solution(state);
+                let weight = self.solution_weight(&state.solution);
+                state.anagrams_number = state.anagrams_number.saturating_add(weight);
+            }
+        }
+    }
+
+    /// Returns the section of the slot-th slot.
+    fn get_section(&self, slot: usize) -> &Section {
+        match &self.slot_sections {
+            Some(slot_sections) => &slot_sections[slot],
+            None => self.dictionarium.get_section(self.word_lengths[slot]),
+        }
+    }
+
+    /// Returns the words of a signature in the slot-th slot.
+    fn get_words(&self, slot: usize, sig: &Signature) -> &Vec<String> {
+        match &self.slot_sections {
+            Some(slot_sections) => &slot_sections[slot][sig],
+            None => self.dictionarium.get_words(sig),
+        }
+    }
+
+    /// Returns true if the words keep the slot order in the output (pattern search).
+    fn is_ordered(&self) -> bool {
+        self.slot_sections.is_some()
+    }
+
+    /// Splits a solution into runs of interchangeable slots (equal consecutive signatures), each one with the index
+    /// of its first slot. Pattern slots are never interchangeable.
+    fn runs<'a>(
+        &self,
+        solution: &'a [Signature],
+    ) -> impl Iterator<Item = (usize, &'a [Signature])> {
+        let ordered = self.is_ordered();
+        solution
+            .chunk_by(move |a, b| !ordered && a == b)
+            .scan(0, |slot, run| {
+                let first = *slot;
+                *slot += run.len();
+                Some((first, run))
+            })
+    }
+
+    /// Picks the targeted anagrams that fall among the ones of the current solution.
+    fn sample_solution(&self, state: &mut SearchState) {
+        let weight = self.solution_weight(&state.solution);
+        let Some(sampler) = &mut state.sampler else {
+            return;
+        };
+
+        while let Some(&target) = sampler.targets.get(sampler.next) {
+            if target >= sampler.offset.saturating_add(weight) {
+                break;
+            }
+            let anagram = self.nth_anagram(&state.solution, target - sampler.offset);
+            sampler.samples.push((target, anagram));
+            sampler.next += 1;
+        }
+
+        sampler.offset = sampler.offset.saturating_add(weight);
+        state.stopped = sampler.next == sampler.targets.len();
+    }
+
+    /// Builds the index-th anagram of a canonical signature solution, out of solution_weight() ones.
+    /// The index is split in one digit per run of equal signatures, and each digit picks a multiset of words.
+    fn nth_anagram(&self, solution: &[Signature], mut index: u64) -> String {
+        let mut anagram: Vec<&str> = Vec::with_capacity(self.words_number + 1);
+
+        if !self.included_text.is_empty() {
+            anagram.push(self.included_text.as_str());
+        }
+
+        for (slot, run) in self.runs(solution) {
+            let words = self.get_words(slot, &run[0]);
+            let (m, k) = (words.len() as u64, run.len() as u64);
+            let combinations = self.run_weight(m, k);
+
+            let multiset = match self.limits.max_repeat {
+                Some(n) => nth_bounded_multiset(m, k, n, index % combinations),
+                None => nth_multiset(m, k, index % combinations),
+            };
+            for i in multiset {
+                anagram.push(&words[i as usize]);
+            }
+            index /= combinations;
+        }
+
+        if !self.is_ordered() {
+            anagram.sort_unstable();
+        }
+        anagram.join(" ")
+    }
+
+    /// Sends a signature solution as a single line, with the groups sorted like the words of an anagram.
+    fn compute_compact_solution(&self, state: &mut SearchState) {
+        let mut groups: Vec<String> = Vec::with_capacity(self.words_number + 1);
+
+        if !self.included_text.is_empty() {
+            groups.push(format_included(&self.included_text));
+        }
+        for (slot, sig) in state.solution.iter().enumerate() {
+            groups.push(format_group(self.get_words(slot, sig)));
+        }
+
+        if !self.is_ordered() {
+            groups.sort_unstable();
+        }
+        self.send(groups.join(" "), state);
+    }
+
+    /// Sends an output line to the IO thread. If the IO thread is gone (e.g. its reader closed the pipe),
+    /// nobody will read what follows: the search stops.
+    fn send(&self, text: String, state: &mut SearchState) {
+        let anagram = Anagram {
+            set_index: self.set_index,
+            text,
+        };
+        if self.sender.send(anagram).is_err() {
+            state.stopped = true;
+        } else {
+            state.stats.anagrams_emitted += 1;
+        }
+    }
+
+    /// Returns the number of distinct anagrams a canonical signature solution expands into.
+    /// A signature repeated k times whose word list has m words gives the k-multisets of m words, not m^k anagrams.
+    fn solution_weight(&self, solution: &[Signature]) -> u64 {
+        let mut weight: u64 = 1;
+        for (slot, run) in self.runs(solution) {
+            let m = self.get_words(slot, &run[0]).len() as u64;
+            weight = weight.saturating_mul(self.run_weight(m, run.len() as u64));
         }
+        weight
+    }
+
+    /// Words of equal length are interchangeable, so only the permutation with non-decreasing signatures is explored:
+    /// it makes anagrams unique across tasks. Returns the lowest signature allowed in the word_index-th slot, if any.
+    /// Pattern slots are not interchangeable, they have no bound.
+    fn lower_bound(&self, word_index: usize, solution: &[Signature]) -> Option<Signature> {
+        (word_index > 0
+            && !self.is_ordered()
+            && self.word_lengths[word_index] == self.word_lengths[word_index - 1])
+            .then(|| solution[word_index - 1])
     }
 
     /// Root for the recursive composition function that builds text anagrams from series of signatures.
@@ -111,35 +683,92 @@ impl SearchThread {
         // Base case
         if index == self.words_number {
             let mut ordered = anagram.clone();
-            ordered.sort_unstable();
+            if !self.is_ordered() {
+                ordered.sort_unstable();
+            }
 
             let canonical = ordered.join(" ");
             debug_assert!(!canonical.is_empty());
 
             if state.anagram_set.insert(canonical.clone()) {
-                let _ = self.sender.send(canonical);
+                self.send(canonical, state);
+                state.anagrams_number += 1;
+            } else {
+                state.stats.duplicates_suppressed += 1;
             }
 
             return;
         }
 
         let sig = &state.solution[index];
-        let words = self.dictionarium.get_words(sig);
+        let words = self.get_words(index, sig);
 
         for w in words {
+            // The included text, if any, is not counted
+            let searched = usize::from(!self.included_text.is_empty());
+            if let Some(n) = self.limits.max_repeat
+                && anagram[searched..].iter().filter(|&a| a == w).count() as u64 >= n
+            {
+                continue;
+            }
             anagram.push(w.clone());
 
             self.output_solution(anagram, index + 1, state);
 
             anagram.pop(); // Backtracking
+
+            if state.stopped {
+                return;
+            }
+        }
+    }
+}
+
+/// Number of multisets of k elements drawn from m, i.e. C(m + k - 1, k). Saturates at u64::MAX.
+pub fn multisets_number(m: u64, k: u64) -> u64 {
+    if m == 0 {
+        return if k == 0 { 1 } else { 0 };
+    }
+
+    // C(n, i) = C(n, i - 1) * (n - i + 1) / i, exact at every step
+    let n = (m + k - 1) as u128;
+    let mut c: u128 = 1;
+    for i in 1..=k as u128 {
+        c = c * (n - i + 1) / i;
+        if c > u64::MAX as u128 {
+            return u64::MAX;
+        }
+    }
+    c as u64
+}
+
+/// Number of multisets of k elements drawn from m in which no element occurs more than max_repeat times.
+/// Saturates at u64::MAX.
+pub fn bounded_multisets_number(m: u64, k: u64, max_repeat: u64) -> u64 {
+    if max_repeat >= k {
+        return multisets_number(m, k);
+    }
+
+    // ways[j] = multisets of j elements drawn from the elements seen so far, each one at most max_repeat times
+    let mut ways = vec![0u64; k as usize + 1];
+    ways[0] = 1;
+    for _ in 0..m {
+        for j in (1..=k as usize).rev() {
+            let from = j.saturating_sub(max_repeat as usize);
+            ways[j] = ways[from..=j]
+                .iter()
+                .fold(0u64, |sum, &w| sum.saturating_add(w));
         }
     }
+    ways[k as usize]
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compact::expand_line;
     use crate::dictionarium::Dictionarium;
+    use crate::pattern::WordPattern;
     use crate::signature::Signature;
     use crossbeam_channel::unbounded;
     use std::collections::HashSet;
@@ -175,11 +804,12 @@ mod tests {
             Arc::new("".to_string()),
             word_lengths,
             sender,
+            Arc::new(MemoCache::new(1024)),
         );
 
         search_thread.run();
 
-        let anagrams_found: HashSet<String> = receiver.try_iter().collect();
+        let anagrams_found: HashSet<String> = receiver.try_iter().map(|a| a.text).collect();
         let expected_anagrams: HashSet<String> = vec!["act", "cat", "tac"]
             .into_iter()
             .map(|s| s.to_string())
@@ -207,11 +837,12 @@ mod tests {
             Arc::new("".to_string()),
             word_lengths,
             sender,
+            Arc::new(MemoCache::new(1024)),
         );
 
         search_thread.run();
 
-        let anagrams_found: HashSet<String> = receiver.try_iter().collect();
+        let anagrams_found: HashSet<String> = receiver.try_iter().map(|a| a.text).collect();
 
         // Valid combinations that form "barman" are (bar/bra) + (man/nam).
         // The output is sorted alphabetically, joined by a space.
@@ -228,5 +859,439 @@ mod tests {
             anagrams_found, expected_anagrams,
             "Should find all multi-word anagrams"
         );
+
+        // One signature solution (abr + amn), expanded into four anagrams
+        let stats = search_thread.get_stats();
+        assert_eq!(stats.signature_solutions, 1);
+        assert_eq!(stats.anagrams_emitted, 4);
+        let mut total = SearchStats::default();
+        total.add(&stats);
+        total.add(&stats);
+        assert_eq!(total.anagrams_emitted, 8);
+    }
+
+    #[test]
+    fn test_search_thread_shared_memo() {
+        // Target: "barman" with one extra "a": every 3+1+3 split, searched twice over the same cache
+        let target_sig = Signature::new("abarman");
+        let dict_words = vec!["a", "bar", "bra", "man", "nam", "ran"];
+        let dict_arc = create_mock_dictionarium(dict_words, "abarman");
+        let memo = Arc::new(MemoCache::new(1024));
+
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            let (sender, receiver) = unbounded();
+            let mut search_thread = SearchThread::new(
+                dict_arc.clone(),
+                Arc::new(target_sig),
+                Arc::new("".to_string()),
+                vec![1, 3, 3],
+                sender,
+                memo.clone(),
+            );
+            search_thread.run();
+            runs.push(
+                receiver
+                    .try_iter()
+                    .map(|a| a.text)
+                    .collect::<HashSet<String>>(),
+            );
+        }
+
+        assert_eq!(runs[0].len(), 4);
+        assert!(runs[0].contains("a bar man"));
+        assert_eq!(
+            runs[0], runs[1],
+            "Cached completions should give the same anagrams"
+        );
+        assert!(
+            memo.get_stats().hits > 0,
+            "The second run should hit the cache"
+        );
+    }
+
+    #[test]
+    fn test_search_thread_order_pruning() {
+        // ab < ac < bd < cd: of the 4 pairs fitting "abcd", only ab+cd and ac+bd are explored down to the leaves
+        let dict_arc = create_mock_dictionarium(vec!["ab", "cd", "ac", "bd"], "abcd");
+        let (sender, receiver) = unbounded();
+        let mut search_thread = SearchThread::new(
+            dict_arc,
+            Arc::new(Signature::new("abcd")),
+            Arc::new("".to_string()),
+            vec![2, 2],
+            sender,
+            Arc::new(MemoCache::new(0)),
+        );
+
+        assert_eq!(search_thread.run(), 2);
+        assert_eq!(receiver.try_iter().count(), 2);
+        let stats = search_thread.get_stats();
+        assert_eq!(stats.nodes_visited, 1 + 4 + 2);
+        assert_eq!(stats.signature_solutions, 2);
+        assert!(stats.pruned_by_order > 0);
+    }
+
+    #[test]
+    fn test_search_thread_split_first_slot() {
+        // Splitting the first slot in two halves must give the same anagrams as a single task, each one only once
+        let target_sig = Signature::new("barman");
+        let dict_words = vec!["bar", "bra", "man", "nam", "ran", "arb"];
+        let dict_arc = create_mock_dictionarium(dict_words, "barman");
+        let first_section_size = dict_arc.get_section(3).len();
+
+        let (sender, receiver) = unbounded();
+        for range in [
+            0..first_section_size / 2,
+            first_section_size / 2..first_section_size,
+        ] {
+            let mut search_thread = SearchThread::new(
+                dict_arc.clone(),
+                Arc::new(target_sig),
+                Arc::new("".to_string()),
+                vec![3, 3],
+                sender.clone(),
+                Arc::new(MemoCache::new(1024)),
+            );
+            search_thread.set_first_slot_range(range);
+            search_thread.run();
+        }
+
+        let anagrams_found: Vec<String> = receiver.try_iter().map(|a| a.text).collect();
+        let unique: HashSet<&String> = anagrams_found.iter().collect();
+        assert_eq!(anagrams_found.len(), 6);
+        assert_eq!(unique.len(), 6);
+    }
+
+    #[test]
+    fn test_search_thread_uncoverable_letter() {
+        // No 3-letter word contains the "q", so [3, 3] can't produce anything, while [2, 4] can
+        let target_sig = Signature::new("aquila");
+        let dict_words = vec!["ali", "lia", "qu", "aila"];
+        let dict_arc = create_mock_dictionarium(dict_words, "aquila");
+        assert!(!target_sig.is_subset_of(&dict_arc.get_coverage(&[3, 3])));
+
+        let mut found = Vec::new();
+        for lengths in [vec![3, 3], vec![2, 4]] {
+            let (sender, receiver) = unbounded();
+            let mut search_thread = SearchThread::new(
+                dict_arc.clone(),
+                Arc::new(target_sig),
+                Arc::new("".to_string()),
+                lengths,
+                sender,
+                Arc::new(MemoCache::new(1024)),
+            );
+            search_thread.run();
+            found.push(receiver.try_iter().map(|a| a.text).collect::<Vec<String>>());
+        }
+
+        assert!(found[0].is_empty());
+        assert_eq!(found[1], vec!["aila qu".to_string()]);
+    }
+
+    #[test]
+    fn test_search_thread_stops_without_receiver() {
+        // 4 anagrams of "barman", but the receiver is gone: the search stops at the first send
+        let dict_arc = create_mock_dictionarium(vec!["bar", "bra", "man", "nam"], "barman");
+        let (sender, receiver) = unbounded();
+        drop(receiver);
+
+        let mut search_thread = SearchThread::new(
+            dict_arc,
+            Arc::new(Signature::new("barman")),
+            Arc::new("".to_string()),
+            vec![3, 3],
+            sender,
+            Arc::new(MemoCache::new(1024)),
+        );
+
+        assert_eq!(search_thread.run(), 1);
+    }
+
+    #[test]
+    fn test_multisets_number() {
+        assert_eq!(multisets_number(2, 2), 3); // aa ab bb
+        assert_eq!(bounded_multisets_number(2, 2, 1), 1); // ab
+        assert_eq!(bounded_multisets_number(3, 3, 2), 7); // all 10 but aaa bbb ccc
+        assert_eq!(bounded_multisets_number(3, 2, 5), 6);
+        assert_eq!(bounded_multisets_number(2, 3, 1), 0);
+        assert_eq!(multisets_number(3, 1), 3);
+        assert_eq!(multisets_number(5, 0), 1);
+        assert_eq!(multisets_number(0, 2), 0);
+        assert_eq!(multisets_number(1_000_000, 10), u64::MAX);
+    }
+
+    #[test]
+    fn test_search_thread_pattern_slots() {
+        let dict_arc = create_mock_dictionarium(vec!["bar", "bra", "rab", "man", "nam"], "barman");
+        let slot_sections = WordPattern::parse("?a? ???")
+            .unwrap()
+            .slot_sections(&dict_arc);
+
+        let (sender, receiver) = unbounded();
+        let mut search_thread = SearchThread::new(
+            dict_arc,
+            Arc::new(Signature::new("barman")),
+            Arc::new("".to_string()),
+            vec![3, 3],
+            sender,
+            Arc::new(MemoCache::new(1024)),
+        );
+        search_thread.set_slot_sections(Arc::new(slot_sections));
+
+        // {bar|rab} {man|nam} and {man|nam} {bar|bra|rab}, words in slot order
+        assert_eq!(search_thread.run(), 10);
+        let anagrams: HashSet<String> = receiver.try_iter().map(|a| a.text).collect();
+        assert_eq!(anagrams.len(), 10);
+        assert!(anagrams.contains("rab man"));
+        assert!(anagrams.contains("nam bra"));
+        assert!(!anagrams.contains("bra man"));
+    }
+
+    #[test]
+    fn test_search_thread_partial() {
+        // "bar" leaves "man" unused, "bra" too; "man" alone leaves "bar"
+        let dict_arc = create_mock_dictionarium(vec!["bar", "bra", "man", "zzz"], "barman");
+        let (sender, receiver) = unbounded();
+        let mut search_thread = SearchThread::new(
+            dict_arc,
+            Arc::new(Signature::new("barman")),
+            Arc::new("".to_string()),
+            vec![3],
+            sender,
+            Arc::new(MemoCache::new(1024)),
+        );
+        assert_eq!(search_thread.run(), 0);
+
+        search_thread.set_partial(true);
+        assert_eq!(search_thread.run(), 3);
+        let anagrams: HashSet<String> = receiver.try_iter().map(|a| a.text).collect();
+        assert_eq!(
+            anagrams,
+            HashSet::from(["bar", "bra", "man"].map(String::from))
+        );
+    }
+
+    #[test]
+    fn test_search_thread_blanks() {
+        // "barma?": the blank stands for the n of "man" or the t of "mat", not for both letters of "tan"
+        let dict_arc = create_mock_dictionarium(vec!["bar", "bra", "man", "mat", "tan"], "barma?");
+        let (sender, receiver) = unbounded();
+        let mut search_thread = SearchThread::new(
+            dict_arc,
+            Arc::new(Signature::new("aabmr")),
+            Arc::new("".to_string()),
+            vec![3, 3],
+            sender,
+            Arc::new(MemoCache::new(1024)),
+        );
+        search_thread.set_blanks(1);
+
+        assert_eq!(search_thread.run(), 4);
+        let anagrams: Vec<String> = receiver.try_iter().map(|a| a.text).collect();
+        assert!(anagrams.iter().all(|a| a.contains("ma")));
+        assert!(!anagrams.iter().any(|a| a.contains("tan")));
+    }
+
+    #[test]
+    fn test_search_thread_tolerance() {
+        // "barman" as [3, 3]: "mat" adds a t and drops the n, "bar" alone can't make up for the n either
+        let dict_arc = create_mock_dictionarium(vec!["bar", "bra", "man", "mat"], "barmant");
+        let mut counts = Vec::new();
+        for tolerance in ["", "add=1", "drop=1", "add=1,drop=1"] {
+            let (sender, _receiver) = unbounded();
+            let mut search_thread = SearchThread::new(
+                dict_arc.clone(),
+                Arc::new(Signature::new("barman")),
+                Arc::new("".to_string()),
+                vec![3, 3],
+                sender,
+                Arc::new(MemoCache::new(1024)),
+            );
+            search_thread.set_tolerance(Tolerance::parse(tolerance).unwrap());
+            counts.push(search_thread.run());
+        }
+        assert_eq!(counts, vec![2, 2, 2, 4]);
+
+        assert_eq!(
+            Tolerance::parse("drop=2, add=1").unwrap(),
+            Tolerance { add: 1, drop: 2 }
+        );
+        assert!(Tolerance::parse("swap=1").is_err());
+        assert!(Tolerance::parse("add=x").is_err());
+    }
+
+    #[test]
+    fn test_search_thread_letter_bank() {
+        // Bank "abnr", "r" required: "baa" reuses the a, and the 3-letter words can only go with "bar"
+        let dict_arc = create_mock_dictionarium(vec!["bar", "ban", "nab", "baa", "barn"], "aabnr");
+        let (sender, receiver) = unbounded();
+        let mut search_thread = SearchThread::new(
+            dict_arc,
+            Arc::new(Signature::new("r")),
+            Arc::new("".to_string()),
+            vec![3, 3],
+            sender,
+            Arc::new(MemoCache::new(1024)),
+        );
+        search_thread.set_letter_bank(true);
+
+        // bar with any 3-letter word, bar included
+        assert_eq!(search_thread.run(), 4);
+        let anagrams: Vec<String> = receiver.try_iter().map(|a| a.text).collect();
+        assert!(anagrams.iter().all(|a| a.contains("bar")));
+        assert!(anagrams.contains(&"bar bar".to_string()));
+    }
+
+    #[test]
+    fn test_search_thread_word_limits() {
+        // "abab" as [2, 2]: ab ab, ab ba, ba ba, aa bb; distinct words leave ab ba and aa bb
+        let dict_arc = create_mock_dictionarium(vec!["ab", "ba", "aa", "bb"], "abab");
+        let mut results = Vec::new();
+        for mode in [OutputMode::Anagrams, OutputMode::CountOnly] {
+            let (sender, receiver) = unbounded();
+            let mut search_thread = SearchThread::new(
+                dict_arc.clone(),
+                Arc::new(Signature::new("abab")),
+                Arc::new("".to_string()),
+                vec![2, 2],
+                sender,
+                Arc::new(MemoCache::new(1024)),
+            );
+            search_thread.set_output_mode(mode);
+            search_thread.set_word_limits(WordLimits {
+                max_repeat: Some(1),
+                max_short: None,
+            });
+            results.push(search_thread.run());
+            assert_eq!(search_thread.run_sample(vec![0, 1]).len(), 2);
+
+            let mut anagrams: Vec<String> = receiver.try_iter().map(|a| a.text).collect();
+            anagrams.sort();
+            if mode == OutputMode::Anagrams {
+                assert_eq!(anagrams, vec!["aa bb", "ab ba"]);
+            }
+
+            // Two words of length 2 or less are too many
+            search_thread.set_word_limits(WordLimits {
+                max_repeat: None,
+                max_short: Some((1, 2)),
+            });
+            results.push(search_thread.run());
+        }
+        assert_eq!(results, vec![2, 0, 2, 0]);
+    }
+
+    #[test]
+    fn test_search_thread_count_only() {
+        // "abab" as [2, 2]: the repeated signature gives ab ab, ab ba, ba ba, not 4 anagrams
+        let dict_arc = create_mock_dictionarium(vec!["ab", "ba", "aa", "bb"], "abab");
+        let mut counts = Vec::new();
+        let mut sent = Vec::new();
+
+        for mode in [OutputMode::Anagrams, OutputMode::CountOnly] {
+            let (sender, receiver) = unbounded();
+            let mut search_thread = SearchThread::new(
+                dict_arc.clone(),
+                Arc::new(Signature::new("abab")),
+                Arc::new("".to_string()),
+                vec![2, 2],
+                sender,
+                Arc::new(MemoCache::new(1024)),
+            );
+            search_thread.set_output_mode(mode);
+            counts.push(search_thread.run());
+            sent.push(receiver.try_iter().count() as u64);
+        }
+
+        // ab ab, ab ba, ba ba, aa bb
+        assert_eq!(counts, vec![4, 4]);
+        assert_eq!(sent, vec![4, 0]);
+    }
+
+    #[test]
+    fn test_search_thread_compact() {
+        let dict_arc = create_mock_dictionarium(vec!["bar", "bra", "man", "nam"], "barman");
+        let (sender, receiver) = unbounded();
+
+        let mut search_thread = SearchThread::new(
+            dict_arc,
+            Arc::new(Signature::new("barman")),
+            Arc::new("".to_string()),
+            vec![3, 3],
+            sender,
+            Arc::new(MemoCache::new(1024)),
+        );
+        search_thread.set_output_mode(OutputMode::Compact);
+        let represented = search_thread.run();
+
+        let lines: Vec<String> = receiver.try_iter().map(|a| a.text).collect();
+        assert_eq!(lines, vec!["{bar|bra} {man|nam}".to_string()]);
+        assert_eq!(represented, 4);
+    }
+
+    #[test]
+    fn test_search_thread_compact_included_text() {
+        // The multi-word included text stays whole when the compact line is expanded, like in the regular output
+        let dict_arc = create_mock_dictionarium(vec!["bar", "bra", "man", "nam"], "barmanzioada");
+        let run = |mode: OutputMode| {
+            let (sender, receiver) = unbounded();
+            let mut search_thread = SearchThread::new(
+                dict_arc.clone(),
+                Arc::new(Signature::new("barman")),
+                Arc::new("zio ada".to_string()),
+                vec![3, 3],
+                sender,
+                Arc::new(MemoCache::new(1024)),
+            );
+            search_thread.set_output_mode(mode);
+            search_thread.run();
+            receiver.try_iter().map(|a| a.text).collect::<Vec<String>>()
+        };
+
+        let regular: HashSet<String> = run(OutputMode::Anagrams).into_iter().collect();
+        let compact = run(OutputMode::Compact);
+        assert_eq!(compact, vec!["\"zio ada\" {bar|bra} {man|nam}".to_string()]);
+        let expanded: HashSet<String> = expand_line(&compact[0], false)
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(expanded, regular);
+        assert!(regular.contains("bar man zio ada"));
+    }
+
+    #[test]
+    fn test_search_thread_sample() {
+        // "abab" as [2, 2] has 4 anagrams: drawing all of them by index gives the regular output
+        let dict_arc = create_mock_dictionarium(vec!["ab", "ba", "aa", "bb"], "abab");
+        let new_thread = || {
+            let (sender, _) = unbounded();
+            SearchThread::new(
+                dict_arc.clone(),
+                Arc::new(Signature::new("abab")),
+                Arc::new("".to_string()),
+                vec![2, 2],
+                sender,
+                Arc::new(MemoCache::new(1024)),
+            )
+        };
+
+        let all = new_thread().run_sample(vec![0, 1, 2, 3]);
+        let indices: Vec<u64> = all.iter().map(|(i, _)| *i).collect();
+        let anagrams: HashSet<String> = all.into_iter().map(|(_, a)| a).collect();
+        assert_eq!(indices, vec![0, 1, 2, 3]);
+        assert_eq!(
+            anagrams,
+            ["aa bb", "ab ab", "ab ba", "ba ba"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect()
+        );
+
+        // Picking a single index gives the same anagram as in the full enumeration, and stops there
+        let full = new_thread().run_sample(vec![0, 1, 2, 3]);
+        let single = new_thread().run_sample(vec![2]);
+        assert_eq!(single, vec![full[2].clone()]);
     }
 }
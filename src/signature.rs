@@ -1,81 +1,174 @@
 use std::fmt;
 use std::hash::{BuildHasherDefault, Hash, Hasher};
 
+/// Maximum count of a single letter in a signature.
+pub const MAX_LETTER_COUNT: u8 = 127;
+
+/// Guard bit of every 8-bit lane.
+const GUARD_BITS: u64 = 0x8080_8080_8080_8080;
+const LANES_PER_WORD: usize = 8;
+
 /// Represents the character signature of a word (a-z only, normalized).
-/// Implemented as an array mapping letter index to letter count.
-#[repr(C)]
-#[repr(align(8))]
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Implemented as 26 packed 8-bit lanes, one per letter, in four 64-bit words. The top bit of every lane is a guard
+/// bit that is always 0 in a valid signature (counts are at most MAX_LETTER_COUNT), so that word-wide subtraction
+/// never borrows across lanes and a subset test is one subtraction and one mask check per word.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Signature {
-    table: [u8; 26],
+    words: [u64; 4],
 }
 
 impl Signature {
-    /// Create a new Signature. The input word MUST be lowercase and normalized.
+    /// Create a new Signature. The input word MUST be lowercase and normalized, and no letter may occur more than
+    /// MAX_LETTER_COUNT times: use try_new() for arbitrary input.
+    ///
+    /// # Panics
+    /// Panics if a letter occurs more than MAX_LETTER_COUNT times.
     pub fn new(word: &str) -> Self {
-        let mut table = [0; 26];
+        Self::try_new(word).unwrap_or_else(|e| panic!("Invalid signature: {}", e))
+    }
+
+    /// Create a new Signature, failing if a letter occurs more than MAX_LETTER_COUNT times.
+    pub fn try_new(word: &str) -> Result<Self, String> {
+        let mut table = [0u32; 26];
         for c in word.bytes() {
-            debug_assert!((b'a'..=b'z').contains(&(c as u8)), "Input must be a-z only");
-            table[(c as u8 - b'a') as usize] += 1;
+            debug_assert!(c.is_ascii_lowercase(), "Input must be a-z only");
+            table[(c - b'a') as usize] += 1;
+        }
+
+        let mut sig = Self::new_empty();
+        for (i, &count) in table.iter().enumerate() {
+            if count > MAX_LETTER_COUNT as u32 {
+                return Err(format!(
+                    "The letter '{}' occurs {} times, maximum is {}",
+                    (b'a' + i as u8) as char,
+                    count,
+                    MAX_LETTER_COUNT
+                ));
+            }
+            sig.set_count(i, count as u8);
         }
-        Self { table }
+        Ok(sig)
     }
 
     /// Creates an empty signature.
     pub fn new_empty() -> Self {
-        let table = [0; 26];
-        Self { table }
+        Self { words: [0; 4] }
+    }
+
+    /// Returns the count of the i-th letter of the alphabet.
+    #[inline(always)]
+    pub fn get_count(&self, letter: usize) -> u8 {
+        (self.words[letter / LANES_PER_WORD] >> (8 * (letter % LANES_PER_WORD))) as u8
     }
 
-    /// Add another Signature to this one.
+    /// Sets the count of the i-th letter of the alphabet.
+    #[inline(always)]
+    fn set_count(&mut self, letter: usize, count: u8) {
+        let shift = 8 * (letter % LANES_PER_WORD);
+        let word = &mut self.words[letter / LANES_PER_WORD];
+        *word = (*word & !(0xff << shift)) | ((count as u64) << shift);
+    }
+
+    /// Add another Signature to this one. The result may exceed MAX_LETTER_COUNT (up to 255), in which case it is
+    /// not a subset of any valid signature and should be subtracted back.
     #[inline(always)]
     pub fn add(&mut self, other: &Signature) {
-        let t = &mut self.table;
-        for (i, &count) in other.table.iter().enumerate() {
-            t[i] += count;
+        for (a, &b) in self.words.iter_mut().zip(other.words.iter()) {
+            debug_assert!((*a | b) & GUARD_BITS == 0, "Addition would overflow");
+            *a += b;
         }
     }
 
     /// Subtract another Signature from this one.
     #[inline(always)]
     pub fn sub(&mut self, other: &Signature) {
-        let t = &mut self.table;
-        for (i, &count) in other.table.iter().enumerate() {
-            debug_assert!(t[i] >= count, "Subtraction would go negative");
-            t[i] -= count;
+        debug_assert!(
+            (0..26).all(|i| self.get_count(i) >= other.get_count(i)),
+            "Subtraction would go negative"
+        );
+        for (a, &b) in self.words.iter_mut().zip(other.words.iter()) {
+            *a -= b; // No lane borrows, as every lane of self is >= the one of other
+        }
+    }
+
+    /// Add another Signature to this one, saturating letter counts at MAX_LETTER_COUNT instead of overflowing.
+    pub fn add_saturating(&mut self, other: &Signature) {
+        for i in 0..26 {
+            let count = (self.get_count(i) + other.get_count(i)).min(MAX_LETTER_COUNT);
+            self.set_count(i, count);
+        }
+    }
+
+    /// Turns this Signature into the union of itself and another one (the maximum count for every letter).
+    pub fn union(&mut self, other: &Signature) {
+        for i in 0..26 {
+            let count = self.get_count(i).max(other.get_count(i));
+            self.set_count(i, count);
+        }
+    }
+
+    /// Returns the letters common to self and other (the minimum count for every letter).
+    pub fn intersection(&self, other: &Signature) -> Signature {
+        let mut common = Self::new_empty();
+        for i in 0..26 {
+            common.set_count(i, self.get_count(i).min(other.get_count(i)));
         }
+        common
+    }
+
+    /// Returns the set of letters of the signature, every one of them counted once.
+    pub fn letter_set(&self) -> Signature {
+        let mut set = Self::new_empty();
+        for i in 0..26 {
+            set.set_count(i, self.get_count(i).min(1));
+        }
+        set
+    }
+
+    /// Returns true if every letter of self occurs in other, however many times: a subset test on the letter sets,
+    /// for letters that can be reused freely.
+    pub fn is_letter_subset_of(&self, other: &Signature) -> bool {
+        (0..26).all(|i| self.get_count(i) == 0 || other.get_count(i) > 0)
     }
 
-    /// Returns true if self is a subset of other.
+    /// Returns true if self is a subset of other, which must be a valid signature.
+    /// Setting the guard bits of other and subtracting self clears a guard bit exactly where self's lane is larger.
     #[inline(always)]
     pub fn is_subset_of(&self, other: &Signature) -> bool {
-        for (a, b) in self.table.iter().zip(other.table.iter()) {
-            if a > b {
-                return false;
-            }
+        let mut ok = true;
+        for (&a, &b) in self.words.iter().zip(other.words.iter()) {
+            debug_assert!(b & GUARD_BITS == 0);
+            ok &= a & GUARD_BITS == 0 && ((b | GUARD_BITS) - a) & GUARD_BITS == GUARD_BITS;
         }
-        true
+        ok
     }
 
-    /// Counts the characters in the signature.
+    /// Returns true if self is a subset of other once up to blanks of its letters are taken from blank tiles, i.e. if
+    /// at most blanks letters of self are missing from other.
     #[inline(always)]
-    pub fn get_char_number(&self) -> usize {
-        //self.table.iter().map(|&c| c as usize).sum()
-        let mut s = 0usize;
+    pub fn is_subset_with_blanks(&self, other: &Signature, blanks: usize) -> bool {
+        self.is_subset_of(other)
+            || (blanks > 0 && self.difference(other).get_char_number() <= blanks)
+    }
+
+    /// Returns the letters of self that other lacks (the count of every letter minus the one of other, or 0).
+    pub fn difference(&self, other: &Signature) -> Signature {
+        let mut diff = Self::new_empty();
         for i in 0..26 {
-            s += self.table[i] as usize;
+            diff.set_count(i, self.get_count(i).saturating_sub(other.get_count(i)));
         }
-        s
+        diff
     }
 
-    /// Returns a string representation.
-    pub fn to_string(&self) -> String {
-        let mut s = String::with_capacity(self.get_char_number());
-        for (i, &count) in self.table.iter().enumerate() {
-            if count > 0 {
-                let c = (b'a' + i as u8) as char;
-                s.extend(std::iter::repeat(c).take(count as usize));
-            }
+    /// Counts the characters in the signature.
+    #[inline(always)]
+    pub fn get_char_number(&self) -> usize {
+        const EVEN_BYTES: u64 = 0x00ff_00ff_00ff_00ff;
+        let mut s = 0usize;
+        for &w in &self.words {
+            // Pairwise sums in 16-bit lanes, then a multiplication adds the four lanes up in the top 16 bits
+            let pairs = (w & EVEN_BYTES) + ((w >> 8) & EVEN_BYTES);
+            s += (pairs.wrapping_mul(0x0001_0001_0001_0001) >> 48) as usize;
         }
         s
     }
@@ -83,23 +176,24 @@ impl Signature {
 
 impl Hash for Signature {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        let data = self.table.as_ptr();
-
-        unsafe {
-            // Write 3 chunks of 8 bytes (24 bytes total)
-            state.write_u64(*(data as *const u64));
-            state.write_u64(*(data.add(8) as *const u64));
-            state.write_u64(*(data.add(16) as *const u64));
-
-            // Write the remaining 2 bytes
-            state.write_u16(*(data.add(24) as *const u16));
+        for &w in &self.words {
+            state.write_u64(w);
         }
     }
 }
 
+/// String representation: every letter repeated as many times as it occurs, in alphabetical order.
 impl fmt::Display for Signature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.to_string())
+        let mut s = String::with_capacity(self.get_char_number());
+        for i in 0..26 {
+            let count = self.get_count(i);
+            if count > 0 {
+                let c = (b'a' + i as u8) as char;
+                s.extend(std::iter::repeat_n(c, count as usize));
+            }
+        }
+        write!(f, "{}", s)
     }
 }
 
@@ -131,13 +225,7 @@ impl Hasher for FnvHasher {
         self.hash = (self.hash ^ i).wrapping_mul(FNV_PRIME);
     }
 
-    // We only need to implement the methods called by Signature::hash.
-    #[inline(always)]
-    fn write_u16(&mut self, i: u16) {
-        self.write_u64(i as u64);
-    }
-
-    // Since Signature::hash only calls write_u64/write_u16, the generic write() is not strictly needed,
+    // Since Signature::hash only calls write_u64, the generic write() is not strictly needed,
     // but the FNV implementation for bytes is also simple:
     fn write(&mut self, bytes: &[u8]) {
         for byte in bytes {
@@ -186,6 +274,71 @@ mod tests {
         assert!(!sig2.is_subset_of(&sig1));
     }
 
+    #[test]
+    fn test_union_and_saturating_add() {
+        let mut sig = Signature::new("aab");
+        sig.union(&Signature::new("abbc"));
+        assert_eq!(sig.to_string(), "aabbc");
+
+        let mut big = Signature::new(&"z".repeat(100));
+        big.add_saturating(&Signature::new(&"z".repeat(100)));
+        assert_eq!(big.get_count(25), MAX_LETTER_COUNT);
+    }
+
+    #[test]
+    fn test_lanes_are_independent() {
+        // Lanes at word boundaries (h = 7, i = 8, p = 15, q = 16, y = 24, z = 25)
+        let full = Signature::new("hhiippqqyz");
+        let sig = Signature::new("hipqz");
+        assert!(sig.is_subset_of(&full));
+        assert!(!Signature::new("iii").is_subset_of(&full));
+        assert!(!Signature::new("zz").is_subset_of(&full));
+
+        // A lane above MAX_LETTER_COUNT after an addition is never a subset, and doesn't spill over its neighbours
+        let mut overflow = Signature::new(&"h".repeat(100));
+        overflow.add(&Signature::new(&"h".repeat(100)));
+        assert_eq!(overflow.get_count(7), 200);
+        assert_eq!(overflow.get_count(8), 0);
+        assert!(!overflow.is_subset_of(&Signature::new(&"h".repeat(127))));
+        assert_eq!(overflow.get_char_number(), 200);
+    }
+
+    #[test]
+    fn test_difference_and_blanks() {
+        let sig = Signature::new("abbcz");
+        let target = Signature::new("abcd");
+        assert_eq!(sig.difference(&target).to_string(), "bz");
+        assert_eq!(target.difference(&sig).to_string(), "d");
+
+        assert!(!sig.is_subset_with_blanks(&target, 1));
+        assert!(sig.is_subset_with_blanks(&target, 2));
+        assert!(Signature::new("abc").is_subset_with_blanks(&target, 0));
+    }
+
+    #[test]
+    fn test_letter_sets() {
+        let word = Signature::new("banana");
+        let bank = Signature::new("abn");
+        assert!(word.is_letter_subset_of(&bank));
+        assert!(!word.is_subset_of(&bank));
+        assert!(!Signature::new("bandana").is_letter_subset_of(&bank));
+
+        assert_eq!(word.letter_set().to_string(), "abn");
+        assert_eq!(word.intersection(&Signature::new("aab")).to_string(), "aab");
+    }
+
+    #[test]
+    #[should_panic(expected = "occurs 128 times")]
+    fn test_new_panics_on_overflow() {
+        Signature::new(&"a".repeat(128));
+    }
+
+    #[test]
+    fn test_try_new_overflow() {
+        assert!(Signature::try_new(&"a".repeat(127)).is_ok());
+        assert!(Signature::try_new(&"a".repeat(128)).is_err());
+    }
+
     #[test]
     #[should_panic(expected = "Subtraction would go negative")]
     fn test_sub_panics_on_negative() {
@@ -0,0 +1,142 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+
+/// Memory used by the in-memory buffers before the largest one is spilled to a temporary file.
+pub const SORT_MEMORY_LIMIT: usize = 256 << 20;
+
+/// Collects output lines per length combination and writes them in a deterministic order: combinations in
+/// their index order, lines sorted within each combination. Buffers that grow too large are sorted and spilled to
+/// anonymous temporary files (runs), which are merged back at the end: an external merge sort.
+pub struct SortedOutput {
+    buffers: Vec<Vec<String>>,
+    runs: Vec<Vec<File>>,
+    buffered_bytes: usize,
+    memory_limit: usize,
+}
+
+impl SortedOutput {
+    /// Constructor
+    pub fn new(sets_number: usize, memory_limit: usize) -> Self {
+        Self {
+            buffers: vec![Vec::new(); sets_number],
+            runs: (0..sets_number).map(|_| Vec::new()).collect(),
+            buffered_bytes: 0,
+            memory_limit,
+        }
+    }
+
+    /// Adds a line of the given length combination.
+    pub fn push(&mut self, set_index: usize, line: String) -> io::Result<()> {
+        self.buffered_bytes += line.len() + size_of::<String>();
+        self.buffers[set_index].push(line);
+
+        if self.buffered_bytes > self.memory_limit {
+            self.spill_largest()?;
+        }
+        Ok(())
+    }
+
+    /// Sorts the largest buffer and writes it to a new run.
+    fn spill_largest(&mut self) -> io::Result<()> {
+        let Some(largest) = (0..self.buffers.len()).max_by_key(|&i| self.buffers[i].len()) else {
+            return Ok(());
+        };
+
+        let mut buffer = std::mem::take(&mut self.buffers[largest]);
+        buffer.sort_unstable();
+
+        let mut writer = BufWriter::new(tempfile::tempfile()?);
+        for line in &buffer {
+            self.buffered_bytes -= line.len() + size_of::<String>();
+            writeln!(writer, "{}", line)?;
+        }
+
+        let mut run = writer.into_inner().map_err(|e| e.into_error())?;
+        run.seek(SeekFrom::Start(0))?;
+        self.runs[largest].push(run);
+        Ok(())
+    }
+
+    /// Hands every line to write_line in order, merging the runs of each combination with its remaining buffer.
+    pub fn write_to(self, mut write_line: impl FnMut(&str) -> io::Result<()>) -> io::Result<()> {
+        for (mut buffer, runs) in self.buffers.into_iter().zip(self.runs) {
+            buffer.sort_unstable();
+
+            if runs.is_empty() {
+                for line in buffer {
+                    write_line(&line)?;
+                }
+                continue;
+            }
+
+            // K-way merge: the buffer is a source like the runs
+            let mut sources: Vec<Box<dyn Iterator<Item = io::Result<String>>>> = runs
+                .into_iter()
+                .map(|run| {
+                    Box::new(BufReader::new(run).lines())
+                        as Box<dyn Iterator<Item = io::Result<String>>>
+                })
+                .collect();
+            sources.push(Box::new(buffer.into_iter().map(Ok)));
+
+            let mut heap = BinaryHeap::new();
+            for (i, source) in sources.iter_mut().enumerate() {
+                if let Some(line) = source.next() {
+                    heap.push(Reverse((line?, i)));
+                }
+            }
+            while let Some(Reverse((line, i))) = heap.pop() {
+                write_line(&line)?;
+                if let Some(next) = sources[i].next() {
+                    heap.push(Reverse((next?, i)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_all(sorted: SortedOutput) -> Vec<String> {
+        let mut lines = Vec::new();
+        sorted
+            .write_to(|line| {
+                lines.push(line.to_string());
+                Ok(())
+            })
+            .unwrap();
+        lines
+    }
+
+    #[test]
+    fn test_sorted_by_set_then_line() {
+        let mut sorted = SortedOutput::new(2, SORT_MEMORY_LIMIT);
+        for (set, line) in [(1, "b c"), (0, "z"), (1, "a d"), (0, "y")] {
+            sorted.push(set, line.to_string()).unwrap();
+        }
+
+        assert_eq!(write_all(sorted), vec!["y", "z", "a d", "b c"]);
+    }
+
+    #[test]
+    fn test_spilled_runs_are_merged() {
+        // A tiny memory limit forces a spill at almost every line
+        let mut sorted = SortedOutput::new(2, 1);
+        let lines = ["delta", "alpha", "echo", "charlie", "bravo"];
+        for line in lines {
+            sorted.push(0, line.to_string()).unwrap();
+        }
+        sorted.push(1, "zulu".to_string()).unwrap();
+
+        assert_eq!(
+            write_all(sorted),
+            vec!["alpha", "bravo", "charlie", "delta", "echo", "zulu"]
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use std::io;
+
+use rusqlite::{Connection, params};
+
+use crate::format::{OutputSink, RecordBuilder, RunHeader};
+
+/// Anagrams inserted per transaction. Committing every row would be orders of magnitude slower.
+const BATCH_SIZE: usize = 10_000;
+
+/// Tables and indexes. A database can hold several runs: the tables are created only if they don't exist yet.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        target TEXT NOT NULL,
+        included_text TEXT NOT NULL,
+        dictionary TEXT NOT NULL,
+        dictionary_checksum TEXT NOT NULL,
+        options TEXT NOT NULL,
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+    CREATE TABLE IF NOT EXISTS anagrams (
+        id INTEGER PRIMARY KEY,
+        run_id INTEGER NOT NULL REFERENCES runs(id),
+        text TEXT NOT NULL,
+        cardinality INTEGER NOT NULL,
+        length_set TEXT NOT NULL,
+        leftover TEXT,
+        blanks TEXT,
+        added TEXT,
+        dropped TEXT
+    );
+    CREATE TABLE IF NOT EXISTS words (
+        anagram_id INTEGER NOT NULL REFERENCES anagrams(id),
+        position INTEGER NOT NULL,
+        word TEXT NOT NULL,
+        normalized TEXT NOT NULL,
+        source TEXT
+    );
+    CREATE INDEX IF NOT EXISTS anagrams_run_cardinality ON anagrams(run_id, cardinality);
+    CREATE INDEX IF NOT EXISTS words_normalized ON words(normalized);
+    CREATE INDEX IF NOT EXISTS words_anagram ON words(anagram_id);
+";
+
+/// Writes the output into an SQLite database: one row in `runs` for the run, one row in `anagrams` per anagram,
+/// one row in `words` per word of an anagram, with its position, normalized form and source dictionary (NULL for the
+/// included text). For example, the 3-word anagrams containing "zelo":
+///
+/// `SELECT a.text FROM anagrams a JOIN words w ON w.anagram_id = a.id WHERE w.normalized = 'zelo' AND a.cardinality = 3`
+pub struct SqliteWriter {
+    connection: Connection,
+    header: RunHeader,
+    builder: RecordBuilder,
+    run_id: i64,
+    pending: usize, // Anagrams inserted in the current transaction
+}
+
+/// SQLite errors are reported as IO errors, like the ones of the other formats.
+fn to_io_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+impl SqliteWriter {
+    /// Opens the database, creating it if needed.
+    pub fn open(path: &str, header: RunHeader) -> io::Result<Self> {
+        let connection = Connection::open(path).map_err(to_io_error)?;
+
+        Ok(Self {
+            connection,
+            builder: RecordBuilder::new(&header),
+            header,
+            run_id: 0,
+            pending: 0,
+        })
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        self.pending = 0;
+        self.connection
+            .execute_batch("COMMIT; BEGIN;")
+            .map_err(to_io_error)
+    }
+}
+
+impl OutputSink for SqliteWriter {
+    fn write_header(&mut self) -> io::Result<()> {
+        self.connection.execute_batch(SCHEMA).map_err(to_io_error)?;
+
+        let options = serde_json::to_string(&self.header.options)?;
+        self.connection
+            .execute(
+                "INSERT INTO runs (target, included_text, dictionary, dictionary_checksum, options)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    self.header.target,
+                    self.header.included_text,
+                    self.header.dictionary,
+                    self.header.dictionary_checksum,
+                    options
+                ],
+            )
+            .map_err(to_io_error)?;
+        self.run_id = self.connection.last_insert_rowid();
+
+        self.connection.execute_batch("BEGIN;").map_err(to_io_error)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let record = self.builder.build(line);
+        let length_set: Vec<String> = record.length_set.iter().map(|l| l.to_string()).collect();
+
+        let mut statement = self
+            .connection
+            .prepare_cached(
+                "INSERT INTO anagrams (run_id, text, cardinality, length_set, leftover, blanks, added, dropped) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )
+            .map_err(to_io_error)?;
+        statement
+            .execute(params![
+                self.run_id,
+                line,
+                record.cardinality as i64,
+                length_set.join(" "),
+                record.leftover,
+                record.blanks,
+                record.added,
+                record.dropped
+            ])
+            .map_err(to_io_error)?;
+        drop(statement);
+        let anagram_id = self.connection.last_insert_rowid();
+
+        let mut statement = self
+            .connection
+            .prepare_cached(
+                "INSERT INTO words (anagram_id, position, word, normalized, source) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )
+            .map_err(to_io_error)?;
+        for (position, word) in record.words.iter().enumerate() {
+            statement
+                .execute(params![
+                    anagram_id,
+                    position as i64,
+                    word,
+                    record.normalized[position],
+                    record.sources[position]
+                ])
+                .map_err(to_io_error)?;
+        }
+        drop(statement);
+
+        self.pending += 1;
+        if self.pending >= BATCH_SIZE {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.commit()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.connection
+            .execute_batch("COMMIT;")
+            .map_err(to_io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::RunOptions;
+    use tempfile::NamedTempFile;
+
+    fn header() -> RunHeader {
+        RunHeader {
+            target: "barman zelo".to_string(),
+            included_text: "zelo".to_string(),
+            dictionary: "it.txt".to_string(),
+            dictionary_checksum: "0".to_string(),
+            options: RunOptions {
+                mincard: 1,
+                maxcard: 3,
+                minwlen: 1,
+                maxwlen: 30,
+                output_mode: "Anagrams".to_string(),
+                sorted: false,
+                sample: None,
+                seed: None,
+                pattern: None,
+                partial: false,
+                max_leftover: None,
+                blanks: 0,
+                tolerance: None,
+                letter_bank: false,
+                required_letters: None,
+                max_repeat: None,
+                max_short: None,
+                short_len: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_tables_are_written_and_queryable() {
+        let tmp_file = NamedTempFile::new().unwrap();
+        let path = tmp_file.path().to_str().unwrap();
+
+        // Two runs in the same database
+        for _ in 0..2 {
+            let mut writer = SqliteWriter::open(path, header()).unwrap();
+            writer.write_header().unwrap();
+            writer.write_line("bar man zelo").unwrap();
+            writer.write_line("bra nam zelo").unwrap();
+            writer.finish().unwrap();
+        }
+
+        let connection = Connection::open(path).unwrap();
+        let count = |sql: &str| -> i64 { connection.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM runs"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM anagrams WHERE run_id = 2"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM words"), 12);
+
+        let text: String = connection
+            .query_row(
+                "SELECT a.text FROM anagrams a JOIN words w ON w.anagram_id = a.id
+                 WHERE w.normalized = 'nam' AND a.cardinality = 3 AND a.run_id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(text, "bra nam zelo");
+
+        // The included text has no source dictionary
+        let (position, source): (i64, Option<String>) = connection
+            .query_row(
+                "SELECT position, source FROM words WHERE word = 'zelo' LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((position, source), (2, None));
+    }
+}
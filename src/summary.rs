@@ -0,0 +1,157 @@
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dictionarium::SectionStats;
+use crate::memo::MemoStats;
+use crate::search::SearchStats;
+use crate::{detail, status};
+
+/// Time spent in each phase of a run, in seconds.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PhaseTimings {
+    pub dictionary: f64,
+    pub planning: f64,
+    pub search: f64,
+    pub sampling: f64,
+    pub output: f64, // Waiting for the IO thread to finish writing, after the search
+    pub total: f64,
+}
+
+/// Statistics of a run, printed at the end and optionally written as JSON to track performance across versions.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RunSummary {
+    pub dictionary_checksum: String, // Dictionarium::get_checksum(), hexadecimal
+    pub sections: Vec<SectionStats>,
+    pub words_read: u64,
+    pub words_kept: u64,
+    pub words_blocked: u64, // By the blocklist or missing from the allowlist, among the words fitting the target
+    pub words_excluded: u64, // By --exclude-word and --exclude-pattern, among the words kept by the target filter
+    pub sets_total: u64,
+    pub sets_infeasible: u64,
+    pub sets_explored: u64,
+    pub tasks: u64,
+    pub search: SearchStats,
+    pub anagrams_found: u64,
+    pub output_truncated: bool, // The reader of stdout closed the pipe, the search stopped before the end
+    pub memo: MemoStats,
+    pub timings: PhaseTimings,
+}
+
+impl RunSummary {
+    /// Prints the summary. The per-section dictionary sizes are only printed with -v.
+    pub fn print(&self) {
+        status!("\n[*] Run summary");
+        status!(
+            "{:<40}{} read, {} kept",
+            "    Words:",
+            self.words_read,
+            self.words_kept
+        );
+        if self.words_blocked > 0 {
+            status!("{:<40}{}", "    Words blocked:", self.words_blocked);
+        }
+        if self.words_excluded > 0 {
+            status!("{:<40}{}", "    Words excluded:", self.words_excluded);
+        }
+        for section in &self.sections {
+            detail!(
+                "{:<40}{} read, {} kept, {} signatures",
+                format!("    Length {}:", section.length),
+                section.words_read,
+                section.words_kept,
+                section.signatures
+            );
+        }
+        status!(
+            "{:<40}{} explored, {} infeasible, {} total ({} tasks)",
+            "    Length combinations:",
+            self.sets_explored,
+            self.sets_infeasible,
+            self.sets_total,
+            self.tasks
+        );
+        status!(
+            "{:<40}{} visited, {} pruned by order, {} pruned by subset, {} pruned by coverage",
+            "    Nodes:",
+            self.search.nodes_visited,
+            self.search.pruned_by_order,
+            self.search.pruned_by_subset,
+            self.search.pruned_by_coverage
+        );
+        if self.search.pruned_by_limits > 0 {
+            status!(
+                "{:<40}{}",
+                "    Pruned by word limits:",
+                self.search.pruned_by_limits
+            );
+        }
+        status!(
+            "{:<40}{}",
+            "    Signature solutions:",
+            self.search.signature_solutions
+        );
+        status!(
+            "{:<40}{} found, {} emitted, {} duplicates suppressed",
+            "    Anagrams:",
+            self.anagrams_found,
+            self.search.anagrams_emitted,
+            self.search.duplicates_suppressed
+        );
+        status!(
+            "{:<40}{} hits, {} misses ({:.1}% hit rate), {} entries, {} replays",
+            "    Memo cache:",
+            self.memo.hits,
+            self.memo.misses,
+            self.memo.hit_rate(),
+            self.memo.entries,
+            self.search.memo_replays
+        );
+        status!(
+            "{:<40}dictionary {:.3}s, planning {:.3}s, search {:.3}s, sampling {:.3}s, output {:.3}s, total {:.3}s",
+            "    Time:",
+            self.timings.dictionary,
+            self.timings.planning,
+            self.timings.search,
+            self.timings.sampling,
+            self.timings.output,
+            self.timings.total
+        );
+    }
+
+    /// Writes the summary as JSON.
+    pub fn write_json(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, json + "\n").map_err(|e| format!("Cannot write file {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_write_json() {
+        let summary = RunSummary {
+            sets_total: 3,
+            anagrams_found: 42,
+            search: SearchStats {
+                nodes_visited: 7,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let tmp_file = NamedTempFile::new().unwrap();
+        let path = tmp_file.path().to_str().unwrap();
+        summary.write_json(path).unwrap();
+
+        let value: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(value["sets_total"], 3);
+        assert_eq!(value["anagrams_found"], 42);
+        assert_eq!(value["search"]["nodes_visited"], 7);
+        assert!(value["timings"]["total"].is_number());
+    }
+}
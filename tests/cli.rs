@@ -0,0 +1,156 @@
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+use tempfile::NamedTempFile;
+
+/// Appends every permutation of the letters to the words.
+fn permutations(prefix: &mut String, letters: &mut Vec<char>, words: &mut Vec<String>) {
+    if letters.is_empty() {
+        words.push(prefix.clone());
+        return;
+    }
+    for i in 0..letters.len() {
+        let letter = letters.remove(i);
+        prefix.push(letter);
+        permutations(prefix, letters, words);
+        prefix.pop();
+        letters.insert(i, letter);
+    }
+}
+
+#[test]
+fn test_broken_pipe_exits_quietly() {
+    // 720 words with the same letters: their pairs make far more output than a pipe buffer holds
+    let mut words = Vec::new();
+    permutations(
+        &mut String::new(),
+        &mut "abcdef".chars().collect(),
+        &mut words,
+    );
+    let mut dictionary = NamedTempFile::new().unwrap();
+    for word in &words {
+        writeln!(dictionary, "{}", word).unwrap();
+    }
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_armamagna"))
+        .args([
+            "abcdefabcdef",
+            "--mincard",
+            "2",
+            "--maxcard",
+            "2",
+            "-t",
+            "3",
+            "-o",
+            "-",
+        ])
+        .arg("-d")
+        .arg(dictionary.path())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // Reads the first line, like `| head -1`, then closes the pipe
+    let mut stdout = child.stdout.take().unwrap();
+    let mut first = [0u8; 14];
+    stdout.read_exact(&mut first).unwrap();
+    drop(stdout);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    // The banner went out before the pipe was closed, nothing after it
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("[*] Source text:"));
+    assert!(!stderr.contains("Search time"));
+    assert!(!stderr.contains("Run summary"));
+    assert!(!stderr.contains("Found"));
+}
+
+#[test]
+fn test_pattern_sets_the_cardinality() {
+    let mut dictionary = NamedTempFile::new().unwrap();
+    for word in ["ab", "cd", "ef", "gh"] {
+        writeln!(dictionary, "{}", word).unwrap();
+    }
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_armamagna"))
+            .args([
+                "abcdefgh",
+                "--pattern",
+                "?? ?? ?? ??",
+                "-t",
+                "3",
+                "-q",
+                "-o",
+                "-",
+            ])
+            .args(extra)
+            .arg("-d")
+            .arg(dictionary.path())
+            .output()
+            .unwrap()
+    };
+
+    // 4 words with the default options
+    let output = run(&[]);
+    assert!(output.status.success());
+    assert_eq!(
+        output
+            .stdout
+            .split(|&b| b == b'\n')
+            .filter(|l| !l.is_empty())
+            .count(),
+        24
+    );
+
+    // Bounds given explicitly must fit
+    let output = run(&["--maxcard", "3"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("the maximum cardinality is 3"));
+    assert!(run(&["--mincard", "4", "--maxcard", "4"]).status.success());
+}
+
+#[test]
+fn test_included_text_is_checked_against_exclusions() {
+    let mut dictionary = NamedTempFile::new().unwrap();
+    for word in ["bar", "man", "bra", "nam"] {
+        writeln!(dictionary, "{}", word).unwrap();
+    }
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_armamagna"))
+            .args([
+                "barman",
+                "-i",
+                "bar",
+                "--mincard",
+                "2",
+                "-t",
+                "3",
+                "-q",
+                "-o",
+                "-",
+            ])
+            .args(extra)
+            .arg("-d")
+            .arg(dictionary.path())
+            .output()
+            .unwrap()
+    };
+
+    assert!(run(&[]).status.success());
+    assert!(run(&["--exclude-word", "nam"]).status.success());
+
+    // Excluded like by the blocklist
+    for extra in [
+        &["--exclude-word", "Bar"][..],
+        &["--exclude-pattern", "^b"][..],
+    ] {
+        let output = run(extra);
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("is excluded from the dictionary")
+        );
+    }
+}